@@ -1,8 +1,8 @@
 use std::{any::type_name, collections::HashMap, rc::Rc, str::FromStr, sync::Arc};
 
-use derive_more::{AsRef, Constructor, From, Into, TryUnwrap, Unwrap};
+use derive_more::{AsRef, Constructor, Display, Error, From, Into, TryUnwrap, Unwrap};
 
-use crate::interpreter::value::{Value, VarState};
+use crate::interpreter::value::{self, ListState, Value, VarState};
 
 // pub mod primitives;
 pub mod project;
@@ -12,21 +12,104 @@ pub struct Target {
     pub name: Arc<str>,
     pub scripts: Vec<Script>,
     pub variables: HashMap<Arc<str>, Variable>,
+    pub lists: HashMap<Arc<str>, List>,
+    /// Sprite-specific state, which is missing for the stage
     pub sprite: Option<Sprite>,
+    /// The target's costumes, or backdrops for the stage
+    pub costumes: Vec<Costume>,
+    pub sounds: Vec<Sound>,
+    pub current_costume: usize,
 }
 
-#[derive(Debug)]
-pub struct Sprite {}
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    pub x: f64,
+    pub y: f64,
+    pub direction: f64,
+    pub size: f64,
+    pub visible: bool,
+    pub draggable: bool,
+    pub rotation_style: RotationStyle,
+    pub layer_order: usize,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            direction: 90.0,
+            size: 100.0,
+            visible: true,
+            draggable: false,
+            rotation_style: RotationStyle::AllAround,
+            layer_order: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotationStyle {
+    #[default]
+    AllAround,
+    LeftRight,
+    DontRotate,
+}
+
+impl RotationStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "all around" => Self::AllAround,
+            "left-right" => Self::LeftRight,
+            "don't rotate" => Self::DontRotate,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AllAround => "all around",
+            Self::LeftRight => "left-right",
+            Self::DontRotate => "don't rotate",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Costume {
+    pub name: Arc<str>,
+    pub asset_id: Arc<str>,
+    pub md5ext: Arc<str>,
+    pub bitmap_resolution: f64,
+    pub rotation_center: (f64, f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Sound {
+    pub name: Arc<str>,
+    pub asset_id: Arc<str>,
+    pub md5ext: Arc<str>,
+    pub rate: u32,
+    pub sample_count: u32,
+}
 
 #[derive(Debug, TryUnwrap)]
 #[try_unwrap(ref)]
 pub enum StartCondition {
     FlagClicked,
     BroadcastReceived(Event),
+    BackdropSwitchedTo(Arc<str>),
+    CloneStarted,
+    KeyPressed(Arc<str>),
+    SpriteClicked,
+    StageClicked,
+    /// A hat whose condition is checked on every frame, such as "when timer > 10". Unlike
+    /// other hats, the hat block is kept at the start of the script to check it.
+    EdgeActivated,
     ProcedureCalled(ProcedurePrototype),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProcedurePrototype {
     pub proc_code: Arc<str>,
     pub arguments: Vec<ProcedureArgument>,
@@ -46,12 +129,18 @@ impl ProcedurePrototype {
         self.arguments.push(arg);
         self
     }
+
+    pub fn with_warp(mut self, warp: bool) -> Self {
+        self.warp = warp;
+        self
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProcedureArgument {
     pub id: Arc<str>,
     pub name: Arc<str>,
+    /// What calls that leave this argument empty pass instead.
     pub default: Arc<str>,
 }
 
@@ -78,8 +167,13 @@ pub struct Script {
 
 #[derive(Debug)]
 pub struct Block {
+    /// The id of the block in the sb3 project. Inline primitives and blocks created by
+    /// the loader don't have one.
+    pub id: Option<Arc<str>>,
     pub opcode: Arc<str>,
     pub proc_code: Option<Arc<str>>,
+    /// The signature of a custom block, present on `procedures_prototype` blocks
+    pub prototype: Option<ProcedurePrototype>,
     /// Inputs that reference other blocks
     pub inputs: HashMap<Arc<str>, Input>,
     /// Inputs that don't reference other blocks
@@ -91,8 +185,11 @@ impl Block {
     pub const NUM_FIELD: &str = "NUM";
     pub const COLOR_FIELD: &str = "COLOUR";
     pub const VAR_FIELD: &str = "VARIABLE";
+    pub const LIST_FIELD: &str = "LIST";
     pub const ARG_NAME_FIELD: &str = "VALUE";
     pub const EVENT_FIELD: &str = "BROADCAST_OPTION";
+    pub const BACKDROP_FIELD: &str = "BACKDROP";
+    pub const KEY_FIELD: &str = "KEY_OPTION";
 
     pub const TEXT: &str = "text";
     pub const NUMBER: &str = "math_number";
@@ -102,16 +199,20 @@ impl Block {
     pub const ANGLE: &str = "math_angle";
     pub const COLOR: &str = "colour_picker";
     pub const VARIABLE: &str = "data_variable";
+    pub const LIST: &str = "data_listcontents";
     pub const STRING_ARG: &str = "argument_reporter_string_number";
     pub const BOOL_ARG: &str = "argument_reporter_boolean";
     pub const EVENT: &str = "event_broadcast_menu";
 
     pub const PROCECURE_DEFN_PROTOTYPE: &str = "custom_block";
+    pub const PROCEDURE_PROTOTYPE: &str = "procedures_prototype";
 
     pub fn new(opcode: impl Into<Arc<str>>) -> Self {
         Self {
+            id: None,
             opcode: opcode.into(),
             proc_code: None,
+            prototype: None,
             inputs: HashMap::new(),
             fields: HashMap::new(),
         }
@@ -119,8 +220,21 @@ impl Block {
 
     pub fn call(proc_code: impl Into<Arc<str>>) -> Self {
         Self {
+            id: None,
             opcode: "procedures_call".into(),
             proc_code: Some(proc_code.into()),
+            prototype: None,
+            inputs: HashMap::new(),
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn prototype(prototype: ProcedurePrototype) -> Self {
+        Self {
+            id: None,
+            opcode: Self::PROCEDURE_PROTOTYPE.into(),
+            proc_code: Some(prototype.proc_code.clone()),
+            prototype: Some(prototype),
             inputs: HashMap::new(),
             fields: HashMap::new(),
         }
@@ -162,6 +276,10 @@ impl Block {
         Block::new("data_variable").with_field("VARIABLE", Field::identified(id, name))
     }
 
+    pub fn list(id: impl Into<Arc<str>>, name: impl Into<Arc<str>>) -> Self {
+        Block::new("data_listcontents").with_field("LIST", Field::identified(id, name))
+    }
+
     pub fn param(name: impl Into<Arc<str>>) -> Self {
         Block::new("argument_reporter_string_number").with_field("VALUE", Field::simple(name))
     }
@@ -228,70 +346,125 @@ impl Block {
         }
     }
 
+    /// The blocks inside a C-shaped input, which may be missing entirely if the slot is empty.
+    pub fn substack(&self, name: &str) -> &[Block] {
+        self.inputs.get(name).map_or(&[], |input| &input.blocks)
+    }
+
     pub fn var_field(&self, name: &str) -> VariableRef {
         self.identified_field(name).into()
     }
 
+    pub fn list_field(&self, name: &str) -> ListRef {
+        self.identified_field(name).into()
+    }
+
     pub fn try_as_primitive(&self) -> Option<Primitive> {
+        // Number slots can hold arbitrary text (for example, when left empty), in which
+        // case the value is kept as text like it is in Scratch. So is text that Scratch
+        // would display differently once converted, such as `1e3` or `05`.
+        let number = |make: fn(f64) -> Option<Primitive>| {
+            let text = self.simple_field(Self::NUM_FIELD);
+            let num = value::parse_number(&text);
+            (value::format_number(num) == *text)
+                .then(|| make(num))
+                .flatten()
+                .unwrap_or(Primitive::Text(text))
+        };
+
         Some(match &*self.opcode {
             Self::TEXT => Primitive::Text(self.simple_field(Self::TEXT_FIELD)),
-            Self::NUMBER => Primitive::Number(self.parsed_field(Self::NUM_FIELD)),
-            Self::INTEGER => Primitive::Integer(self.parsed_field(Self::NUM_FIELD)),
-            Self::WHOLE_NUMBER => Primitive::WholeNumber(self.parsed_field(Self::NUM_FIELD)),
+            Self::NUMBER => number(|n| Some(Primitive::Number(n))),
+            Self::INTEGER => {
+                number(|n| (n as u64 as f64 == n).then_some(Primitive::Integer(n as u64)))
+            }
+            Self::WHOLE_NUMBER => {
+                number(|n| (n as i64 as f64 == n).then_some(Primitive::WholeNumber(n as i64)))
+            }
             Self::POSITIVE_NUMBER => {
-                let pos_num: f64 = self.parsed_field(Self::NUM_FIELD);
-                if pos_num.is_sign_negative() {
-                    panic!("{pos_num:?} is not a valid positive number");
-                }
-                Primitive::PositiveNumber(pos_num)
+                number(|n| n.is_sign_positive().then_some(Primitive::PositiveNumber(n)))
             }
-            Self::ANGLE => Primitive::Angle(self.parsed_field(Self::NUM_FIELD)),
+            Self::ANGLE => number(|n| Some(Primitive::Angle(n))),
             Self::VARIABLE => Primitive::Variable(self.identified_field(Self::VAR_FIELD).into()),
+            Self::LIST => Primitive::List(self.identified_field(Self::LIST_FIELD).into()),
             Self::EVENT => Primitive::Event(self.identified_field(Self::EVENT_FIELD).into()),
             _ => return None,
         })
     }
 
-    pub fn try_as_start_condition(&self) -> Option<StartCondition> {
-        Some(match &*self.opcode {
+    pub fn try_as_start_condition(&self) -> Result<Option<StartCondition>, StartConditionError> {
+        Ok(Some(match &*self.opcode {
             "event_whenflagclicked" => StartCondition::FlagClicked,
             "event_whenbroadcastreceived" => {
-                let field = &self.fields[Self::EVENT_FIELD]
-                    .try_to_named_resource()
-                    .expect("BroadcastReceived block missing event id");
+                let field = self
+                    .fields
+                    .get(Self::EVENT_FIELD)
+                    .and_then(Field::try_to_named_resource)
+                    .ok_or(StartConditionError::MissingEvent)?;
+
+                StartCondition::BroadcastReceived(Event::from(field))
+            }
+            "event_whenbackdropswitchesto" => {
+                let backdrop = self
+                    .fields
+                    .get(Self::BACKDROP_FIELD)
+                    .ok_or(StartConditionError::MissingBackdrop)?;
 
-                StartCondition::BroadcastReceived(Event::from(field.clone()))
+                StartCondition::BackdropSwitchedTo(backdrop.value.clone())
             }
+            "control_start_as_clone" => StartCondition::CloneStarted,
+            "event_whenkeypressed" => {
+                let key = self
+                    .fields
+                    .get(Self::KEY_FIELD)
+                    .ok_or(StartConditionError::MissingKey)?;
+
+                StartCondition::KeyPressed(key.value.clone())
+            }
+            "event_whenthisspriteclicked" => StartCondition::SpriteClicked,
+            "event_whenstageclicked" => StartCondition::StageClicked,
+            "event_whengreaterthan" => StartCondition::EdgeActivated,
             "procedures_definition" => {
-                let custom_block = &self.inputs[Self::PROCECURE_DEFN_PROTOTYPE].unwrap_single_ref();
-
-                let mut prototype = ProcedurePrototype::new(
-                    custom_block
-                        .proc_code
-                        .clone()
-                        .expect("procedure definition missing proc code"),
-                );
-
-                for (id, input) in &custom_block.inputs {
-                    let reporter = input.unwrap_single_ref();
-                    let value = reporter.simple_field(Self::ARG_NAME_FIELD);
-                    let arg = ProcedureArgument::new(id.clone(), value);
-                    prototype = prototype.with_arg(arg);
-                }
+                // The prototype's mutation lists the arguments in order, unlike its inputs.
+                let prototype = self
+                    .inputs
+                    .get(Self::PROCECURE_DEFN_PROTOTYPE)
+                    .and_then(Input::try_single_ref)
+                    .and_then(|custom_block| custom_block.prototype.clone())
+                    .ok_or(StartConditionError::MissingPrototype)?;
 
                 StartCondition::ProcedureCalled(prototype)
             }
-            _ => return None,
-        })
+            _ => return Ok(None),
+        }))
     }
 }
 
+/// The reason a hat block couldn't be interpreted as the start condition of a script.
+#[derive(Debug, Display, Error)]
+pub enum StartConditionError {
+    #[display("broadcast hat is missing its event")]
+    MissingEvent,
+    #[display("backdrop hat is missing its backdrop")]
+    MissingBackdrop,
+    #[display("key hat is missing its key")]
+    MissingKey,
+    #[display("procedure definition is missing its prototype")]
+    MissingPrototype,
+}
+
 impl From<VariableRef> for Block {
     fn from(value: VariableRef) -> Self {
         Self::var(value.0.id, value.0.name)
     }
 }
 
+impl From<ListRef> for Block {
+    fn from(value: ListRef) -> Self {
+        Self::list(value.0.id, value.0.name)
+    }
+}
+
 impl From<Event> for Block {
     fn from(value: Event) -> Self {
         Self::event(value.0.id, value.0.name)
@@ -307,6 +480,7 @@ pub enum Primitive {
     PositiveNumber(f64),
     Angle(f64),
     Variable(VariableRef),
+    List(ListRef),
     Event(Event),
 }
 
@@ -321,6 +495,13 @@ impl Input {
         assert!(self.blocks.len() == 1, "expected single block");
         &self.blocks[0]
     }
+
+    pub fn try_single_ref(&self) -> Option<&Block> {
+        match &self.blocks[..] {
+            [block] => Some(block),
+            _ => None,
+        }
+    }
 }
 
 impl From<Block> for Input {
@@ -449,6 +630,64 @@ impl VariableRef {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct List {
+    pub reference: ListRef,
+    pub initial_items: Vec<Value>,
+}
+
+impl List {
+    pub fn new(reference: ListRef, initial_items: Vec<Value>) -> Self {
+        Self {
+            reference,
+            initial_items,
+        }
+    }
+
+    pub fn empty(reference: ListRef) -> Self {
+        Self {
+            reference,
+            initial_items: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> Arc<str> {
+        self.reference.id()
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        self.reference.name()
+    }
+
+    pub fn initialize(&self) -> ListState {
+        ListState {
+            name: self.name(),
+            items: self.initial_items.clone().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, From, Into, AsRef, PartialEq, Eq, Hash)]
+pub struct ListRef(NamedResource);
+
+impl ListRef {
+    pub fn new(id: impl Into<Arc<str>>, name: impl Into<Arc<str>>) -> Self {
+        Self(NamedResource::new(id.into(), name.into()))
+    }
+
+    pub fn into_inner(self) -> NamedResource {
+        self.0
+    }
+
+    pub fn id(&self) -> Arc<str> {
+        self.0.id.clone()
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        self.0.name.clone()
+    }
+}
+
 #[derive(Debug, Clone, From, Into, AsRef)]
 pub struct Event(NamedResource);
 
@@ -8,9 +8,9 @@ use std::{
 use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    ast::{Block, Event, StartCondition, Target, Variable},
+    ast::{Block, Event, List, StartCondition, Target, Variable},
     blocks::BlockLibrary,
-    codegen::{ProjectContext, ScriptCompiler, TargetCodegenContext},
+    codegen::{ProcedureSignature, ProjectContext, ScriptCompiler, TargetCodegenContext},
     interpreter::{
         Program, TargetScope,
         opcode::Trigger,
@@ -23,6 +23,7 @@ pub struct ScratchProject {
     pub targets: Vec<Target>,
     pub events: IndexMap<Arc<str>, Event>,
     pub global_vars: HashMap<Arc<str>, Variable>,
+    pub global_lists: HashMap<Arc<str>, List>,
 }
 
 impl ScratchProject {
@@ -36,10 +37,13 @@ impl ScratchProject {
         let text_constants = self.find_text_constants();
         let project_ctx = Arc::new(ProjectContext::new(
             self.global_vars.values().cloned(),
+            self.global_lists.values().cloned(),
+            self.events.keys().cloned(),
             text_constants.clone(),
         ));
 
         let global_vars = self.global_vars.values().map(|v| v.initialize()).collect();
+        let global_lists = self.global_lists.values().map(|l| l.initialize()).collect();
         let event_values = self
             .events
             .values()
@@ -50,20 +54,25 @@ impl ScratchProject {
         // into this list of scopes by the interpreter.
         let target_scopes = self.targets.iter().map(TargetScope::from).collect();
 
+        let target_procedures = self.find_procedures();
+
         scope(|scope| {
             let mut target_tasks = Vec::new();
 
-            for (target_id, target) in self.targets.iter().enumerate() {
+            for ((target_id, target), procedures) in
+                self.targets.iter().enumerate().zip(target_procedures)
+            {
                 let project_ctx = project_ctx.clone();
                 let type_library = type_library.clone();
 
                 let task = scope.spawn(move || {
-                    let mut initial_vars = self.global_vars.clone();
-                    initial_vars.extend(target.variables.clone());
-
+                    // Sprite-local variables and lists must be passed in the same order they're
+                    // stored in the target's scope so their handles line up at runtime.
                     let ctx = Arc::new(TargetCodegenContext::new(
                         project_ctx,
-                        initial_vars.values().cloned(),
+                        target.variables.values().cloned(),
+                        target.lists.values().cloned(),
+                        procedures,
                     ));
 
                     let mut compile_tasks = Vec::new();
@@ -77,14 +86,17 @@ impl ScratchProject {
                                 .start_condition
                                 .try_unwrap_procedure_called_ref()
                                 .ok();
-                            let param_count = proc_info.map_or(0, |proto| proto.arguments.len());
+                            let params = proc_info.map_or(vec![], |proto| {
+                                proto.arguments.iter().map(|arg| arg.name.clone()).collect()
+                            });
+                            let param_count = params.len();
 
-                            // If this script is going to run in warp mode, there's no reason to generate yields
-                            // because they will be ignored at runtime.
                             let warp_enabled = proc_info.is_some_and(|p| p.warp);
 
+                            // Yields are still generated in warp mode because the interpreter
+                            // needs them to force a redraw once the warp timer runs out.
                             let mut compiler =
-                                ScriptCompiler::new(ctx, type_library, warp_enabled, param_count);
+                                ScriptCompiler::new(ctx, type_library, false, params);
                             compiler.compile(script);
 
                             let name = format!("Script {script_id} of Target {}", target.name);
@@ -96,7 +108,8 @@ impl ScratchProject {
                                 compiler.get_locals(),
                                 compiler.data.into_boxed_slice(),
                                 warp_enabled,
-                            );
+                            )
+                            .with_source_map(compiler.source_map);
 
                             let trigger = match &script.start_condition {
                                 StartCondition::FlagClicked => Some(Trigger::OnStart),
@@ -107,6 +120,14 @@ impl ScratchProject {
                                         .expect("event should have index");
                                     Some(Trigger::Event(idx.into()))
                                 }
+                                StartCondition::BackdropSwitchedTo(backdrop) => {
+                                    Some(Trigger::backdrop_switched_to(backdrop))
+                                }
+                                StartCondition::CloneStarted => Some(Trigger::CloneStarted),
+                                StartCondition::KeyPressed(key) => Some(Trigger::key_pressed(key)),
+                                StartCondition::SpriteClicked => Some(Trigger::SpriteClicked),
+                                StartCondition::StageClicked => Some(Trigger::StageClicked),
+                                StartCondition::EdgeActivated => Some(Trigger::EdgeActivated),
                                 StartCondition::ProcedureCalled(_proto) => None,
                             };
 
@@ -127,8 +148,13 @@ impl ScratchProject {
                 text_constants.iter().cloned().map(Value::String).collect(),
                 event_values,
                 global_vars,
+                global_lists,
                 target_scopes,
             );
+            program.set_layers(self.layer_order());
+
+            // Scripts must be registered in the same order `find_procedures` assigned ids in.
+            let mut next_proc_id = 0;
 
             for task in target_tasks {
                 let compile_tasks = task.join().unwrap();
@@ -137,6 +163,9 @@ impl ScratchProject {
                     let (trigger, proc) = task.join().unwrap();
 
                     let handle = program.register(proc);
+                    debug_assert_eq!(handle.id().get(), next_proc_id);
+                    next_proc_id += 1;
+
                     if let Some(trigger) = trigger {
                         program.add_trigger(handle, trigger);
                     }
@@ -147,6 +176,46 @@ impl ScratchProject {
         })
     }
 
+    /// Finds the custom blocks defined in each target and the ids their procedures will
+    /// have once registered, so that scripts can be compiled in parallel while still being
+    /// able to call each other.
+    fn find_procedures(&self) -> Vec<HashMap<Arc<str>, ProcedureSignature>> {
+        let mut next_proc_id = 0;
+
+        self.targets
+            .iter()
+            .map(|target| {
+                let mut procedures = HashMap::new();
+
+                for script in &target.scripts {
+                    if let StartCondition::ProcedureCalled(proto) = &script.start_condition {
+                        // Scratch uses the first definition if a block is defined twice.
+                        procedures
+                            .entry(proto.proc_code.clone())
+                            .or_insert_with(|| ProcedureSignature {
+                                id: next_proc_id.into(),
+                                arguments: proto.arguments.clone(),
+                            });
+                    }
+
+                    next_proc_id += 1;
+                }
+
+                procedures
+            })
+            .collect()
+    }
+
+    /// Sorts the targets by the layer they start on, which puts the stage at the back.
+    fn layer_order(&self) -> Vec<usize> {
+        let mut layers = (0..self.targets.len()).collect::<Vec<_>>();
+        layers.sort_by_key(|&id| {
+            let sprite = self.targets[id].sprite.as_ref();
+            sprite.map_or(0, |sprite| sprite.layer_order)
+        });
+        layers
+    }
+
     fn find_text_constants(&self) -> Arc<IndexSet<Arc<str>>> {
         let mut constants = IndexSet::new();
 
@@ -159,6 +228,13 @@ impl ScratchProject {
                     constants.insert(text);
                 }
 
+                // Blocks can push the values of their fields as text, such as menus
+                for field in block.fields.values() {
+                    if field.id.is_none() {
+                        constants.insert(field.value.clone());
+                    }
+                }
+
                 // (Otherwise,) find child blocks that might be text
                 for input in block.inputs.values() {
                     let substack = &input.blocks;
@@ -169,6 +245,13 @@ impl ScratchProject {
 
         for target in &self.targets {
             for script in &target.scripts {
+                // Argument defaults are pushed by calls that leave an argument empty
+                if let StartCondition::ProcedureCalled(proto) = &script.start_condition {
+                    for arg in &proto.arguments {
+                        constants.insert(arg.default.clone());
+                    }
+                }
+
                 traverse_substack(&script.blocks, &mut constants);
             }
         }
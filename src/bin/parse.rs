@@ -4,7 +4,7 @@ fn main() {
     let sb3 = include_str!("../../lang/project.json");
 
     let sb3: Sb3Project = serde_json::from_str(sb3).unwrap();
-    let project = ScratchProject::from(sb3);
+    let project = ScratchProject::try_from(sb3).unwrap();
 
     println!("{project:#?}");
 }
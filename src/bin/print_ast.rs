@@ -90,14 +90,19 @@ fn main() {
         targets: vec![Target {
             name: "Cat".into(),
             scripts: vec![flag_script, event_script, type_script],
-            sprite: Some(Sprite {}),
+            sprite: Some(Sprite::default()),
             variables: HashMap::from([]),
+            lists: HashMap::new(),
+            costumes: vec![],
+            sounds: vec![],
+            current_costume: 0,
         }],
         global_vars: HashMap::from([
             ("001".into(), Variable::new(thingtotype, Value::default())),
             ("002".into(), Variable::new(textsofar, Value::default())),
             ("003".into(), Variable::new(c, Value::default())),
         ]),
+        global_lists: HashMap::new(),
     };
 
     dbg!(ast);
@@ -4,10 +4,19 @@ use bon::bon;
 use indexmap::IndexMap;
 
 use crate::{
+    ast::Primitive,
     codegen::{BlockType, CompileContext, PlaceholderLabel},
-    interpreter::{opcode::Opcode, value::Value, RuntimeContext},
+    interpreter::{opcode::Opcode, RuntimeContext},
 };
 
+mod event;
+mod looks;
+mod motion;
+mod operator;
+mod procedure;
+mod sensing;
+mod sound;
+
 pub type BlockCompileLogic = dyn Fn(CompileContext<'_>) + Send + Sync;
 pub type BlockRuntimeLogic = dyn FnMut(RuntimeContext<'_>) + Send + Sync;
 
@@ -102,10 +111,13 @@ impl BlockLibrary {
                 Vec<Option<Box<BlockRuntimeLogic>>>,
             )>();
 
+        let opcodes = type_lib.keys().cloned().collect();
+
         (
             BlockTypeLibrary { blocks: type_lib },
             BlockRuntimeLibrary {
                 blocks: runtime_lib,
+                opcodes,
             },
         )
     }
@@ -121,14 +133,6 @@ impl Default for BlockLibrary {
     fn default() -> Self {
         let mut library = Self::empty();
 
-        library
-            .register_block("looks_say")
-            .runtime_logic(|mut ctx| {
-                let param = ctx.task_mut().pop();
-                println!("{}", ctx.program().dbg_string(&param));
-            })
-            .finish();
-
         library
             .register_block("data_setvariableto")
             .compile_logic(|ctx| {
@@ -136,7 +140,6 @@ impl Default for BlockLibrary {
                 let value = &ctx.block.inputs["VALUE"];
 
                 ctx.compiler.build_set_var(variable, value);
-                ctx.compiler.build_yield();
             })
             .finish();
 
@@ -147,18 +150,116 @@ impl Default for BlockLibrary {
                 let value = &ctx.block.inputs["VALUE"];
 
                 ctx.compiler.build_change_var(variable, value);
-                ctx.compiler.build_yield();
+            })
+            .finish();
+
+        library
+            .register_block("data_addtolist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let item = &ctx.block.inputs["ITEM"];
+
+                ctx.compiler.build_push(item);
+                ctx.compiler.build_list_op(Opcode::ListAdd, list);
+            })
+            .finish();
+
+        library
+            .register_block("data_deleteoflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let index = &ctx.block.inputs["INDEX"];
+
+                ctx.compiler.build_push(index);
+                ctx.compiler.build_list_op(Opcode::ListDelete, list);
+            })
+            .finish();
+
+        library
+            .register_block("data_deletealloflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+
+                ctx.compiler.build_list_op(Opcode::ListDeleteAll, list);
+            })
+            .finish();
+
+        library
+            .register_block("data_insertatlist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let item = &ctx.block.inputs["ITEM"];
+                let index = &ctx.block.inputs["INDEX"];
+
+                ctx.compiler.build_push(item);
+                ctx.compiler.build_push(index);
+                ctx.compiler.build_list_op(Opcode::ListInsert, list);
+            })
+            .finish();
+
+        library
+            .register_block("data_replaceitemoflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let index = &ctx.block.inputs["INDEX"];
+                let item = &ctx.block.inputs["ITEM"];
+
+                ctx.compiler.build_push(index);
+                ctx.compiler.build_push(item);
+                ctx.compiler.build_list_op(Opcode::ListReplace, list);
+            })
+            .finish();
+
+        library
+            .register_reporter("data_itemoflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let index = &ctx.block.inputs["INDEX"];
+
+                ctx.compiler.build_push(index);
+                ctx.compiler.build_list_op(Opcode::ListItem, list);
+            })
+            .finish();
+
+        library
+            .register_reporter("data_itemnumoflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let item = &ctx.block.inputs["ITEM"];
+
+                ctx.compiler.build_push(item);
+                ctx.compiler.build_list_op(Opcode::ListIndexOf, list);
+            })
+            .finish();
+
+        library
+            .register_reporter("data_lengthoflist")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+
+                ctx.compiler.build_list_op(Opcode::ListLength, list);
+            })
+            .finish();
+
+        library
+            .register_reporter("data_listcontainsitem")
+            .compile_logic(|ctx| {
+                let list = ctx.block.list_field("LIST");
+                let item = &ctx.block.inputs["ITEM"];
+
+                ctx.compiler.build_push(item);
+                ctx.compiler.build_list_op(Opcode::ListContains, list);
             })
             .finish();
 
         library
             .register_block("control_forever")
             .compile_logic(|ctx| {
-                let substack = &ctx.block.inputs["SUBSTACK"];
+                let substack = ctx.block.substack("SUBSTACK");
 
                 let loop_start = ctx.compiler.label_here();
-                ctx.compiler.compile_substack(&substack.blocks);
-                ctx.compiler.build_jump(loop_start);
+                ctx.compiler.compile_substack(substack);
+                ctx.compiler.build_loop_end(loop_start);
             })
             .finish();
 
@@ -166,7 +267,7 @@ impl Default for BlockLibrary {
             .register_block("control_repeat")
             .compile_logic(|ctx| {
                 let times = &ctx.block.inputs["TIMES"];
-                let substack = &ctx.block.inputs["SUBSTACK"];
+                let substack = ctx.block.substack("SUBSTACK");
 
                 // Keep track of how many repeats we have remaining
                 let repeats_left = ctx.compiler.claim_local();
@@ -183,10 +284,10 @@ impl Default for BlockLibrary {
                 ctx.compiler.write_op(Opcode::DecLocal);
                 ctx.compiler.write_imm(repeats_left.into());
 
-                ctx.compiler.compile_substack(&substack.blocks);
+                ctx.compiler.compile_substack(substack);
 
                 // Back to start
-                ctx.compiler.build_jump(loop_start);
+                ctx.compiler.build_loop_end(loop_start);
 
                 // Clean up
                 ctx.compiler.commit_placeholder(loop_end);
@@ -194,6 +295,92 @@ impl Default for BlockLibrary {
             })
             .finish();
 
+        library
+            .register_block("control_repeat_until")
+            .compile_logic(|ctx| {
+                let condition = ctx.block.inputs.get("CONDITION");
+                let substack = ctx.block.substack("SUBSTACK");
+
+                let loop_start = ctx.compiler.label_here();
+                let loop_end = PlaceholderLabel::new();
+
+                ctx.compiler.build_jump_if_input(condition, true, &loop_end);
+                ctx.compiler.compile_substack(substack);
+                ctx.compiler.build_loop_end(loop_start);
+
+                ctx.compiler.commit_placeholder(loop_end);
+            })
+            .finish();
+
+        library
+            .register_block("control_while")
+            .compile_logic(|ctx| {
+                let condition = ctx.block.inputs.get("CONDITION");
+                let substack = ctx.block.substack("SUBSTACK");
+
+                let loop_start = ctx.compiler.label_here();
+                let loop_end = PlaceholderLabel::new();
+
+                ctx.compiler.build_jump_if_input(condition, false, &loop_end);
+                ctx.compiler.compile_substack(substack);
+                ctx.compiler.build_loop_end(loop_start);
+
+                ctx.compiler.commit_placeholder(loop_end);
+            })
+            .finish();
+
+        library
+            .register_block("control_wait_until")
+            .compile_logic(|ctx| {
+                let condition = ctx.block.inputs.get("CONDITION");
+
+                // Check the condition once per frame until it's true
+                let loop_start = ctx.compiler.label_here();
+                let loop_end = PlaceholderLabel::new();
+
+                ctx.compiler.build_jump_if_input(condition, true, &loop_end);
+                ctx.compiler.build_loop_end(loop_start);
+
+                ctx.compiler.commit_placeholder(loop_end);
+            })
+            .finish();
+
+        library
+            .register_block("control_if")
+            .compile_logic(|ctx| {
+                let condition = ctx.block.inputs.get("CONDITION");
+                let substack = ctx.block.substack("SUBSTACK");
+
+                let end = PlaceholderLabel::new();
+
+                ctx.compiler.build_jump_if_input(condition, false, &end);
+                ctx.compiler.compile_substack(substack);
+
+                ctx.compiler.commit_placeholder(end);
+            })
+            .finish();
+
+        library
+            .register_block("control_if_else")
+            .compile_logic(|ctx| {
+                let condition = ctx.block.inputs.get("CONDITION");
+                let substack = ctx.block.substack("SUBSTACK");
+                let else_substack = ctx.block.substack("SUBSTACK2");
+
+                let else_start = PlaceholderLabel::new();
+                let end = PlaceholderLabel::new();
+
+                ctx.compiler.build_jump_if_input(condition, false, &else_start);
+                ctx.compiler.compile_substack(substack);
+                ctx.compiler.build_jump(&end);
+
+                ctx.compiler.commit_placeholder(else_start);
+                ctx.compiler.compile_substack(else_substack);
+
+                ctx.compiler.commit_placeholder(end);
+            })
+            .finish();
+
         library
             .register_block("control_wait")
             .compile_logic(|ctx| {
@@ -205,16 +392,78 @@ impl Default for BlockLibrary {
             .finish();
 
         library
-            .register_reporter("operator_join")
-            .inputs_order(["STRING1".into(), "STRING2".into()])
+            .register_block("control_stop")
+            .compile_logic(|mut ctx| {
+                let option = ctx.block.simple_field("STOP_OPTION");
+
+                // Like in Scratch, stopping this script only returns from the custom block
+                // it's used in, and it finishes the task at the top level of a script.
+                if &*option == "this script" {
+                    ctx.compiler.write_op(Opcode::Return);
+                } else {
+                    ctx.compiler.build_push(Primitive::Text(option));
+                    ctx.build_call_self();
+                }
+            })
+            .runtime_logic(|mut ctx| {
+                let [option] = ctx.task_mut().pop_strings();
+                let task_id = ctx.task().id();
+                let target_id = ctx.target_id();
+
+                match &*option {
+                    "all" => ctx.program_mut().stop_all(),
+                    // The stage's menu has its own name for this option
+                    "other scripts in sprite" | "other scripts in stage" => {
+                        ctx.program_mut().stop_other_tasks(target_id, task_id);
+                    }
+                    _ => {}
+                }
+            })
+            .finish();
+
+        library
+            .register_block("control_create_clone_of")
             .runtime_logic(|mut ctx| {
-                let [str1, str2] = ctx.task_mut().pop_strings();
+                let [option] = ctx.task_mut().pop_strings();
+                let parent_id = if &*option == "_myself_" {
+                    Some(ctx.target_id())
+                } else {
+                    ctx.program().find_target(&option)
+                };
+
+                // Clones of the stage and clones past the limit are silently not created
+                if let Some(parent_id) = parent_id {
+                    ctx.program_mut().create_clone(parent_id);
+                }
+            })
+            .finish();
 
-                let joined = format!("{str1}{str2}");
-                ctx.task_mut().push(Value::String(joined.into()));
+        library
+            .register_reporter("control_create_clone_of_menu")
+            .compile_logic(|ctx| {
+                let value = ctx.block.simple_field("CLONE_OPTION");
+                ctx.compiler.build_push(Primitive::Text(value));
             })
             .finish();
 
+        library
+            .register_block("control_delete_this_clone")
+            .runtime_logic(|mut ctx| {
+                // Only clones can be deleted, so this does nothing in the original sprite.
+                // Deleting a clone also stops the task running this block.
+                let target_id = ctx.target_id();
+                ctx.program_mut().delete_clone(target_id);
+            })
+            .finish();
+
+        event::register(&mut library);
+        looks::register(&mut library);
+        motion::register(&mut library);
+        operator::register(&mut library);
+        procedure::register(&mut library);
+        sensing::register(&mut library);
+        sound::register(&mut library);
+
         library
     }
 }
@@ -247,12 +496,50 @@ impl Debug for BlockTypeLibrary {
 
 pub struct BlockRuntimeLibrary {
     blocks: Vec<Option<Box<BlockRuntimeLogic>>>,
+    /// The opcode of each block, so that builtin ids can be traced back to their blocks.
+    opcodes: Vec<Arc<str>>,
 }
 
 impl BlockRuntimeLibrary {
     pub fn get(&mut self, idx: usize) -> Option<&mut BlockRuntimeLogic> {
         self.blocks[idx].as_deref_mut()
     }
+
+    /// The opcode of the block with the given builtin id.
+    pub fn opcode(&self, idx: usize) -> Option<&str> {
+        self.opcodes.get(idx).map(|opcode| &**opcode)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Whether the block with the given builtin id has runtime logic.
+    pub fn contains(&self, idx: usize) -> bool {
+        self.blocks.get(idx).is_some_and(Option::is_some)
+    }
+
+    /// Reorders the library so that builtin ids match the given table of opcodes, which
+    /// lets bytecode compiled against another version of the library call the right
+    /// blocks. Opcodes that aren't in this library are left without runtime logic.
+    pub fn rebind(mut self, opcodes: &[Arc<str>]) -> Self {
+        let blocks = opcodes
+            .iter()
+            .map(|opcode| {
+                let idx = self.opcodes.iter().position(|other| other == opcode)?;
+                self.blocks[idx].take()
+            })
+            .collect();
+
+        Self {
+            blocks,
+            opcodes: opcodes.to_vec(),
+        }
+    }
 }
 
 impl Debug for BlockRuntimeLibrary {
@@ -0,0 +1,159 @@
+use crate::{
+    ast::Primitive,
+    blocks::BlockLibrary,
+    codegen::{CompileContext, PlaceholderLabel},
+    interpreter::{opcode::Opcode, value::Value},
+};
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_block("event_broadcast")
+        .compile_logic(|mut ctx| compile_broadcast(&mut ctx))
+        .runtime_logic(|mut ctx| {
+            // Only broadcasts with computed names get here. Unknown names start nothing.
+            let [name] = ctx.task_mut().pop_strings();
+            ctx.broadcast_named(&name);
+        })
+        .finish();
+
+    library
+        .register_block("event_broadcastandwait")
+        .compile_logic(|mut ctx| {
+            compile_broadcast(&mut ctx);
+
+            // Wait a frame at a time until every script the broadcast started has finished
+            let loop_start = ctx.compiler.label_here();
+            let loop_end = PlaceholderLabel::new();
+
+            ctx.build_call_self();
+            ctx.compiler.build_jump_if(false, &loop_end);
+            ctx.compiler.build_loop_end(loop_start);
+
+            ctx.compiler.commit_placeholder(loop_end);
+        })
+        .runtime_logic(|mut ctx| {
+            let waiting = ctx
+                .task()
+                .started_tasks()
+                .iter()
+                .any(|&task| ctx.program().is_running(task));
+            ctx.task_mut().push(Value::Boolean(waiting));
+        })
+        .finish();
+
+    library
+        .register_block("event_whengreaterthan")
+        .compile_logic(|mut ctx| {
+            let menu = ctx.block.simple_field("WHENGREATERTHANMENU");
+
+            // The script is started every frame, and only carries on past its hat on the
+            // frame the condition becomes true
+            let started = PlaceholderLabel::new();
+
+            ctx.compiler.build_push(&ctx.block.inputs["VALUE"]);
+            ctx.compiler.build_push(Primitive::Text(menu));
+            ctx.build_call_self();
+            ctx.compiler.build_jump_if(true, &started);
+            ctx.compiler.write_op(Opcode::Return);
+
+            ctx.compiler.commit_placeholder(started);
+        })
+        .runtime_logic(|mut ctx| {
+            let [value, menu] = ctx.task_mut().pop_values();
+            let sensor = match &*menu.cast_string().to_lowercase() {
+                "timer" => ctx.program().timer(),
+                // Like the loudness block without a microphone
+                "loudness" => -1.0,
+                _ => f64::NAN,
+            };
+
+            let rising = ctx.update_edge(sensor > value.cast_number());
+            ctx.task_mut().push(Value::Boolean(rising));
+        })
+        .finish();
+}
+
+/// Sends the broadcast in the block's input. Broadcasts picked from the menu are resolved
+/// while compiling, but reporters dropped into the input are looked up by name at runtime.
+fn compile_broadcast(ctx: &mut CompileContext) {
+    let input = &ctx.block.inputs["BROADCAST_INPUT"];
+    let event = input
+        .try_single_ref()
+        .and_then(|block| block.try_as_primitive())
+        .and_then(|primitive| primitive.try_unwrap_event().ok());
+
+    if let Some(event) = event {
+        let id = ctx.compiler.target.project.event(&event);
+        ctx.compiler.write_op(Opcode::DispatchEvent);
+        ctx.compiler.write_imm(id.get() as u32);
+        return;
+    }
+
+    let broadcast = ctx
+        .compiler
+        .block_library
+        .block("event_broadcast")
+        .expect("event_broadcast should be registered");
+
+    ctx.compiler.build_push(input);
+    ctx.compiler.write_op(Opcode::CallBuiltin);
+    ctx.compiler.write_imm(broadcast.id());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::project::ScratchProject,
+        interpreter::{Program, clock::VirtualClock, value::Value},
+        sb3::Sb3Project,
+    };
+
+    /// Starts a slow script with a broadcast, then waits on a broadcast whose computed name
+    /// doesn't exist before setting `done`.
+    const PROJECT: &str = r#"{"targets": [
+        {"isStage": true, "name": "Stage", "variables": {"v": ["done", 0]}, "lists": {},
+            "broadcasts": {"b": "slow"}, "blocks": {}},
+        {"isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "broadcasts": {},
+            "blocks": {
+                "a0": {"opcode": "event_whenflagclicked", "next": "a1", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+                "a1": {"opcode": "event_broadcast", "next": "a2", "parent": "a0",
+                    "inputs": {"BROADCAST_INPUT": [1, [11, "slow", "b"]]}, "fields": {},
+                    "topLevel": false},
+                "a2": {"opcode": "event_broadcastandwait", "next": "a3", "parent": "a1",
+                    "inputs": {"BROADCAST_INPUT": [3, "join", [11, "slow", "b"]]},
+                    "fields": {}, "topLevel": false},
+                "a3": {"opcode": "data_setvariableto", "next": null, "parent": "a2",
+                    "inputs": {"VALUE": [1, [10, "1"]]}, "fields": {"VARIABLE": ["done", "v"]},
+                    "topLevel": false},
+                "join": {"opcode": "operator_join", "next": null, "parent": "a2",
+                    "inputs": {"STRING1": [1, [10, "no"]], "STRING2": [1, [10, "pe"]]},
+                    "fields": {}, "topLevel": false},
+                "b0": {"opcode": "event_whenbroadcastreceived", "next": "b1", "parent": null,
+                    "inputs": {}, "fields": {"BROADCAST_OPTION": ["slow", "b"]},
+                    "topLevel": true},
+                "b1": {"opcode": "control_wait", "next": null, "parent": "b0",
+                    "inputs": {"DURATION": [1, [5, "10"]]}, "fields": {}, "topLevel": false}
+            }}
+    ]}"#;
+
+    fn compile(json: &str) -> Program {
+        let sb3: Sb3Project = serde_json::from_str(json).unwrap();
+        let mut program = ScratchProject::try_from(sb3).unwrap().compile();
+        program.set_clock(VirtualClock::new());
+        program
+    }
+
+    #[test]
+    fn unknown_broadcast_and_wait() {
+        let mut program = compile(PROJECT);
+        program.click_green_flag();
+        program.run_frame();
+
+        let stage = program.stage_id();
+        assert_eq!(
+            program.variable_by_name(stage, "done"),
+            Some(Value::from("1"))
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    ast::Primitive,
+    blocks::BlockLibrary,
+    codegen::CompileContext,
+    interpreter::{
+        opcode::{Opcode, Trigger},
+        sprite::{Bubble, BubbleKind, GraphicEffect},
+        value::Value,
+        Program, RuntimeContext, TargetScope,
+    },
+};
+
+/// The longest message that fits in a speech bubble, in UTF-16 code units.
+const MAX_BUBBLE_LENGTH: usize = 330;
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_block("looks_say")
+        .runtime_logic(|mut ctx| {
+            let message = ctx.task_mut().pop();
+            show_bubble(&mut ctx, BubbleKind::Say, &message, None);
+        })
+        .finish();
+
+    library
+        .register_block("looks_think")
+        .runtime_logic(|mut ctx| {
+            let message = ctx.task_mut().pop();
+            show_bubble(&mut ctx, BubbleKind::Think, &message, None);
+        })
+        .finish();
+
+    library
+        .register_block("looks_sayforsecs")
+        .compile_logic(compile_timed_bubble)
+        .runtime_logic(|mut ctx| {
+            let [message, secs] = ctx.task_mut().pop_values();
+            show_bubble(&mut ctx, BubbleKind::Say, &message, Some(secs.cast_number()));
+        })
+        .finish();
+
+    library
+        .register_block("looks_thinkforsecs")
+        .compile_logic(compile_timed_bubble)
+        .runtime_logic(|mut ctx| {
+            let [message, secs] = ctx.task_mut().pop_values();
+            show_bubble(&mut ctx, BubbleKind::Think, &message, Some(secs.cast_number()));
+        })
+        .finish();
+
+    library
+        .register_block("looks_switchcostumeto")
+        .runtime_logic(|mut ctx| {
+            let costume = ctx.task_mut().pop();
+            let target = ctx.target_mut();
+
+            // Costumes named after the special options take priority over them
+            if let Value::String(name) = &costume
+                && target.costume_index(name).is_none()
+            {
+                match &**name {
+                    "next costume" => return target.next_costume(),
+                    "previous costume" => return target.previous_costume(),
+                    _ => {}
+                }
+            }
+
+            set_costume(target, &costume);
+        })
+        .finish();
+
+    library
+        .register_block("looks_nextcostume")
+        .runtime_logic(|mut ctx| ctx.target_mut().next_costume())
+        .finish();
+
+    library
+        .register_block("looks_switchbackdropto")
+        .runtime_logic(|mut ctx| {
+            let backdrop = ctx.task_mut().pop();
+            let program = ctx.program_mut();
+
+            if let Value::String(name) = &backdrop
+                && program.stage().costume_index(name).is_none()
+            {
+                match &**name {
+                    "next backdrop" => program.stage_mut().next_costume(),
+                    "previous backdrop" => program.stage_mut().previous_costume(),
+                    "random backdrop" => random_backdrop(program),
+                    _ => set_costume(program.stage_mut(), &backdrop),
+                }
+            } else {
+                set_costume(program.stage_mut(), &backdrop);
+            }
+
+            backdrop_switched(program);
+        })
+        .finish();
+
+    library
+        .register_block("looks_nextbackdrop")
+        .runtime_logic(|mut ctx| {
+            let program = ctx.program_mut();
+            program.stage_mut().next_costume();
+            backdrop_switched(program);
+        })
+        .finish();
+
+    library
+        .register_block("looks_changesizeby")
+        .runtime_logic(|mut ctx| {
+            let [change] = ctx.task_mut().pop_numbers();
+            if let Some(sprite) = ctx.sprite() {
+                let size = sprite.size + change;
+                ctx.target_mut().set_size(size);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("looks_setsizeto")
+        .runtime_logic(|mut ctx| {
+            let [size] = ctx.task_mut().pop_numbers();
+            ctx.target_mut().set_size(size);
+        })
+        .finish();
+
+    library
+        .register_block("looks_changeeffectby")
+        .compile_logic(|mut ctx| {
+            let effect = ctx.block.simple_field("EFFECT");
+
+            ctx.compiler.build_push(Primitive::Text(effect));
+            ctx.compiler.build_push(&ctx.block.inputs["CHANGE"]);
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [effect, change] = ctx.task_mut().pop_values();
+            let Some(effect) = GraphicEffect::from_name(&effect.cast_string()) else {
+                return;
+            };
+
+            let effects = ctx.target_mut().effects_mut();
+            effects.set(effect, effects.get(effect) + change.cast_number());
+        })
+        .finish();
+
+    library
+        .register_block("looks_seteffectto")
+        .compile_logic(|mut ctx| {
+            let effect = ctx.block.simple_field("EFFECT");
+
+            ctx.compiler.build_push(Primitive::Text(effect));
+            ctx.compiler.build_push(&ctx.block.inputs["VALUE"]);
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [effect, value] = ctx.task_mut().pop_values();
+            let Some(effect) = GraphicEffect::from_name(&effect.cast_string()) else {
+                return;
+            };
+
+            ctx.target_mut().effects_mut().set(effect, value.cast_number());
+        })
+        .finish();
+
+    library
+        .register_block("looks_cleargraphiceffects")
+        .runtime_logic(|mut ctx| ctx.target_mut().effects_mut().clear())
+        .finish();
+
+    library
+        .register_block("looks_show")
+        .runtime_logic(|mut ctx| {
+            if let Some(sprite) = ctx.sprite_mut() {
+                sprite.visible = true;
+            }
+        })
+        .finish();
+
+    library
+        .register_block("looks_hide")
+        .runtime_logic(|mut ctx| {
+            if let Some(sprite) = ctx.sprite_mut() {
+                sprite.visible = false;
+
+                // Hidden sprites can't keep talking
+                let target_id = ctx.target_id();
+                ctx.program_mut().set_bubble(target_id, None);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("looks_gotofrontback")
+        .compile_logic(|mut ctx| {
+            let front_back = ctx.block.simple_field("FRONT_BACK");
+
+            ctx.compiler.build_push(Primitive::Text(front_back));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [front_back] = ctx.task_mut().pop_strings();
+            let target_id = ctx.target_id();
+
+            match &*front_back {
+                "front" => ctx.program_mut().move_to_front(target_id),
+                "back" => ctx.program_mut().move_to_back(target_id),
+                _ => {}
+            }
+        })
+        .finish();
+
+    library
+        .register_block("looks_goforwardbackwardlayers")
+        .compile_logic(|mut ctx| {
+            let direction = ctx.block.simple_field("FORWARD_BACKWARD");
+
+            ctx.compiler.build_push(Primitive::Text(direction));
+            ctx.compiler.build_push(&ctx.block.inputs["NUM"]);
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [direction, layers] = ctx.task_mut().pop_values();
+            let layers = layers.cast_number() as isize;
+            let target_id = ctx.target_id();
+
+            match &*direction.cast_string() {
+                "forward" => ctx.program_mut().move_layers(target_id, layers),
+                "backward" => ctx.program_mut().move_layers(target_id, -layers),
+                _ => {}
+            }
+        })
+        .finish();
+
+    library
+        .register_reporter("looks_costumenumbername")
+        .compile_logic(|mut ctx| {
+            let number_name = ctx.block.simple_field("NUMBER_NAME");
+
+            ctx.compiler.build_push(Primitive::Text(number_name));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [number_name] = ctx.task_mut().pop_strings();
+            let value = costume_number_name(ctx.target(), &number_name);
+            ctx.task_mut().push(value);
+        })
+        .finish();
+
+    library
+        .register_reporter("looks_backdropnumbername")
+        .compile_logic(|mut ctx| {
+            let number_name = ctx.block.simple_field("NUMBER_NAME");
+
+            ctx.compiler.build_push(Primitive::Text(number_name));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [number_name] = ctx.task_mut().pop_strings();
+            let value = costume_number_name(ctx.program().stage(), &number_name);
+            ctx.task_mut().push(value);
+        })
+        .finish();
+
+    library
+        .register_reporter("looks_size")
+        .runtime_logic(|mut ctx| {
+            let size = ctx.sprite().map_or(100.0, |sprite| sprite.size.round());
+            ctx.task_mut().push(Value::Number(size));
+        })
+        .finish();
+
+    for (opcode, field) in [("looks_costume", "COSTUME"), ("looks_backdrops", "BACKDROP")] {
+        library
+            .register_reporter(opcode)
+            .compile_logic(move |ctx| {
+                let value = ctx.block.simple_field(field);
+                ctx.compiler.build_push(Primitive::Text(value));
+            })
+            .finish();
+    }
+}
+
+/// Compiles a bubble that's shown for a number of seconds. The block's runtime logic
+/// shows the bubble, and then the script waits while it's visible.
+fn compile_timed_bubble(mut ctx: CompileContext) {
+    let secs = ctx.compiler.claim_local();
+
+    ctx.compiler.build_push(&ctx.block.inputs["MESSAGE"]);
+    ctx.compiler.build_set_local(secs, &ctx.block.inputs["SECS"]);
+    ctx.compiler.build_push(secs);
+    ctx.build_call_self();
+
+    ctx.compiler.build_push(secs);
+    ctx.compiler.write_op(Opcode::Sleep);
+
+    ctx.compiler.release_local(secs);
+}
+
+/// Shows a bubble next to the current sprite, replacing any bubble it already has. Empty
+/// messages clear the bubble instead.
+fn show_bubble(ctx: &mut RuntimeContext, kind: BubbleKind, message: &Value, secs: Option<f64>) {
+    let text = bubble_text(message);
+    let now = ctx.program().now();
+
+    let bubble = (!text.is_empty()).then(|| Bubble {
+        kind,
+        text,
+        owner: ctx.task().id(),
+        expires_at: secs.map(|secs| now + Duration::try_from_secs_f64(secs).unwrap_or_default()),
+    });
+
+    let target_id = ctx.target_id();
+    ctx.program_mut().set_bubble(target_id, bubble);
+}
+
+/// Formats a message for a bubble. Like Scratch, numbers with a fractional part are shown
+/// with two decimal places unless that would round them to zero.
+fn bubble_text(message: &Value) -> Arc<str> {
+    let text = match *message {
+        Value::Number(num) if num.is_finite() && num.abs() >= 0.01 && num.fract() != 0.0 => {
+            to_fixed_2(num).into()
+        }
+        _ => message.cast_string(),
+    };
+
+    let units = text.encode_utf16().collect::<Vec<_>>();
+    if units.len() > MAX_BUBBLE_LENGTH {
+        String::from_utf16_lossy(&units[..MAX_BUBBLE_LENGTH]).into()
+    } else {
+        text
+    }
+}
+
+/// Formats a number with two decimal places like JavaScript's `toFixed(2)`, which rounds
+/// ties away from zero instead of to even.
+fn to_fixed_2(num: f64) -> String {
+    // Only multiples of 1/8 can be exactly halfway between two hundredths
+    let eighths = num * 8.0;
+    if eighths.fract() == 0.0 && eighths % 2.0 != 0.0 {
+        let hundredths = (num.abs() * 100.0).ceil().copysign(num);
+        format!("{:.2}", hundredths / 100.0)
+    } else {
+        format!("{num:.2}")
+    }
+}
+
+/// Switches to a costume by name, or by its 1-based number if no costume has that name.
+fn set_costume(target: &mut TargetScope, costume: &Value) {
+    if let Value::String(name) = costume
+        && let Some(idx) = target.costume_index(name)
+    {
+        target.set_costume(idx);
+    } else if let Some(number) = costume.try_as_number() {
+        target.set_costume_wrapped(number - 1.0);
+    }
+}
+
+/// Switches the stage to a random backdrop other than the current one.
+fn random_backdrop(program: &mut Program) {
+    let count = program.stage().costumes().len();
+    if count <= 1 {
+        return;
+    }
+
+    let current = program.stage().current_costume();
+    let mut idx = (program.rng_mut().next_f64() * (count - 1) as f64) as usize;
+    if idx >= current {
+        idx += 1;
+    }
+
+    program.stage_mut().set_costume(idx);
+}
+
+/// Starts the scripts waiting for the stage to switch to its current backdrop.
+fn backdrop_switched(program: &mut Program) {
+    if let Some(backdrop) = program.stage().costume() {
+        let trigger = Trigger::backdrop_switched_to(&backdrop.name);
+        program.dispatch(trigger);
+    }
+}
+
+fn costume_number_name(target: &TargetScope, number_name: &str) -> Value {
+    if number_name == "name" {
+        let name = target.costume().map_or("".into(), |costume| costume.name.clone());
+        Value::String(name)
+    } else {
+        Value::Number((target.current_costume() + 1) as f64)
+    }
+}
@@ -0,0 +1,411 @@
+use crate::{
+    ast::{Block, Primitive, RotationStyle},
+    blocks::BlockLibrary,
+    codegen::{CompileContext, LocalHandle, PlaceholderLabel, ScriptCompiler},
+    interpreter::{
+        opcode::Opcode,
+        sprite::{limit_precision, STAGE_HEIGHT, STAGE_WIDTH},
+        value::Value,
+        RuntimeContext,
+    },
+};
+
+const MOUSE: &str = "_mouse_";
+const RANDOM: &str = "_random_";
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_block("motion_movesteps")
+        .runtime_logic(|mut ctx| {
+            let [steps] = ctx.task_mut().pop_numbers();
+            let Some((x, y)) = sprite_position(&ctx) else {
+                return;
+            };
+
+            let radians = (90.0 - sprite_direction(&ctx)).to_radians();
+            let (dx, dy) = (steps * radians.cos(), steps * radians.sin());
+            ctx.target_mut().set_xy(x + dx, y + dy);
+        })
+        .finish();
+
+    library
+        .register_block("motion_turnright")
+        .runtime_logic(|mut ctx| {
+            let [degrees] = ctx.task_mut().pop_numbers();
+            if let Some(sprite) = ctx.sprite_mut() {
+                sprite.set_direction(sprite.direction + degrees);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_turnleft")
+        .runtime_logic(|mut ctx| {
+            let [degrees] = ctx.task_mut().pop_numbers();
+            if let Some(sprite) = ctx.sprite_mut() {
+                sprite.set_direction(sprite.direction - degrees);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_gotoxy")
+        .inputs_order(["X".into(), "Y".into()])
+        .runtime_logic(|mut ctx| {
+            let [x, y] = ctx.task_mut().pop_numbers();
+            ctx.target_mut().set_xy(x, y);
+        })
+        .finish();
+
+    library
+        .register_block("motion_goto")
+        .runtime_logic(|mut ctx| {
+            let [to] = ctx.task_mut().pop_strings();
+            if let Some((x, y)) = menu_position(&mut ctx, &to) {
+                ctx.target_mut().set_xy(x, y);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_glidesecstoxy")
+        .compile_logic(|ctx| {
+            compile_glide(ctx, |compiler, block, end_x, end_y| {
+                compiler.build_set_local(end_x, &block.inputs["X"]);
+                compiler.build_set_local(end_y, &block.inputs["Y"]);
+            });
+        })
+        .runtime_logic(|ctx| {
+            glide_step(ctx, |_ctx, end_x, end_y| {
+                Some((end_x.cast_number(), end_y.cast_number()))
+            });
+        })
+        .finish();
+
+    library
+        .register_block("motion_glideto")
+        .compile_logic(|ctx| {
+            // The menu is resolved to a position when the glide starts, so until then
+            // the first local holds the menu's value.
+            compile_glide(ctx, |compiler, block, end_x, _end_y| {
+                compiler.build_set_local(end_x, &block.inputs["TO"]);
+            });
+        })
+        .runtime_logic(|ctx| {
+            glide_step(ctx, |ctx, to, _| menu_position(ctx, &to.cast_string()));
+        })
+        .finish();
+
+    library
+        .register_block("motion_pointindirection")
+        .runtime_logic(|mut ctx| {
+            let [direction] = ctx.task_mut().pop_numbers();
+            if let Some(sprite) = ctx.sprite_mut() {
+                sprite.set_direction(direction);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_pointtowards")
+        .runtime_logic(|mut ctx| {
+            let [towards] = ctx.task_mut().pop_strings();
+            if ctx.sprite().is_none() {
+                return;
+            }
+
+            let direction = if &*towards == RANDOM {
+                (ctx.program_mut().rng_mut().next_f64() * 360.0).round() - 180.0
+            } else {
+                let Some((target_x, target_y)) = menu_position(&mut ctx, &towards) else {
+                    return;
+                };
+
+                let (x, y) = sprite_position(&ctx).unwrap();
+                let (dx, dy) = (target_x - x, target_y - y);
+                90.0 - dy.atan2(dx).to_degrees()
+            };
+
+            ctx.sprite_mut().unwrap().set_direction(direction);
+        })
+        .finish();
+
+    library
+        .register_block("motion_changexby")
+        .runtime_logic(|mut ctx| {
+            let [dx] = ctx.task_mut().pop_numbers();
+            if let Some((x, y)) = sprite_position(&ctx) {
+                ctx.target_mut().set_xy(x + dx, y);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_setx")
+        .runtime_logic(|mut ctx| {
+            let [x] = ctx.task_mut().pop_numbers();
+            if let Some((_, y)) = sprite_position(&ctx) {
+                ctx.target_mut().set_xy(x, y);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_changeyby")
+        .runtime_logic(|mut ctx| {
+            let [dy] = ctx.task_mut().pop_numbers();
+            if let Some((x, y)) = sprite_position(&ctx) {
+                ctx.target_mut().set_xy(x, y + dy);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_sety")
+        .runtime_logic(|mut ctx| {
+            let [y] = ctx.task_mut().pop_numbers();
+            if let Some((x, _)) = sprite_position(&ctx) {
+                ctx.target_mut().set_xy(x, y);
+            }
+        })
+        .finish();
+
+    library
+        .register_block("motion_ifonedgebounce")
+        .runtime_logic(if_on_edge_bounce)
+        .finish();
+
+    library
+        .register_block("motion_setrotationstyle")
+        .compile_logic(|mut ctx| {
+            let style = ctx.block.simple_field("STYLE");
+
+            ctx.compiler.build_push(Primitive::Text(style));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [style] = ctx.task_mut().pop_strings();
+            let style = RotationStyle::from_name(&style);
+
+            if let Some(sprite) = ctx.sprite_mut()
+                && let Some(style) = style
+            {
+                sprite.rotation_style = style;
+            }
+        })
+        .finish();
+
+    library
+        .register_reporter("motion_xposition")
+        .runtime_logic(|mut ctx| {
+            let x = sprite_position(&ctx).map_or(0.0, |(x, _)| limit_precision(x));
+            ctx.task_mut().push(Value::Number(x));
+        })
+        .finish();
+
+    library
+        .register_reporter("motion_yposition")
+        .runtime_logic(|mut ctx| {
+            let y = sprite_position(&ctx).map_or(0.0, |(_, y)| limit_precision(y));
+            ctx.task_mut().push(Value::Number(y));
+        })
+        .finish();
+
+    library
+        .register_reporter("motion_direction")
+        .runtime_logic(|mut ctx| {
+            let direction = sprite_direction(&ctx);
+            ctx.task_mut().push(Value::Number(direction));
+        })
+        .finish();
+
+    // Menus report the value of their field, which is a target name or a special value
+    // like `_mouse_`.
+    for (opcode, field) in [
+        ("motion_goto_menu", "TO"),
+        ("motion_glideto_menu", "TO"),
+        ("motion_pointtowards_menu", "TOWARDS"),
+    ] {
+        library
+            .register_reporter(opcode)
+            .compile_logic(move |ctx| {
+                let value = ctx.block.simple_field(field);
+                ctx.compiler.build_push(Primitive::Text(value));
+            })
+            .finish();
+    }
+}
+
+fn sprite_position(ctx: &RuntimeContext) -> Option<(f64, f64)> {
+    ctx.sprite().map(|sprite| (sprite.x, sprite.y))
+}
+
+/// The direction of the current sprite. The stage always points right.
+fn sprite_direction(ctx: &RuntimeContext) -> f64 {
+    ctx.sprite().map_or(90.0, |sprite| sprite.direction)
+}
+
+/// Resolves the value of a motion menu to a position on the stage, or `None` if it names
+/// a sprite that doesn't exist.
+fn menu_position(ctx: &mut RuntimeContext, menu: &str) -> Option<(f64, f64)> {
+    match menu {
+        MOUSE => Some(ctx.program().mouse_position()),
+        RANDOM => {
+            let rng = ctx.program_mut().rng_mut();
+            let x = (STAGE_WIDTH * rng.next_f64() - STAGE_WIDTH / 2.0).round();
+            let y = (STAGE_HEIGHT * rng.next_f64() - STAGE_HEIGHT / 2.0).round();
+            Some((x, y))
+        }
+        name => {
+            let program = ctx.program();
+            let target_id = program.find_target(name)?;
+            let sprite = program.target(target_id).sprite()?;
+            Some((sprite.x, sprite.y))
+        }
+    }
+}
+
+fn if_on_edge_bounce(mut ctx: RuntimeContext) {
+    let target = ctx.target();
+    let Some(sprite) = target.sprite() else {
+        return;
+    };
+
+    let bounds = sprite.bounds(target.costume());
+    let distances = [
+        (Edge::Left, STAGE_WIDTH / 2.0 + bounds.left),
+        (Edge::Top, STAGE_HEIGHT / 2.0 - bounds.top),
+        (Edge::Right, STAGE_WIDTH / 2.0 - bounds.right),
+        (Edge::Bottom, STAGE_HEIGHT / 2.0 + bounds.bottom),
+    ];
+
+    // Find the edge that the sprite is closest to, preferring the earlier edge on ties
+    let (edge, distance) = distances
+        .into_iter()
+        .map(|(edge, distance)| (edge, distance.max(0.0)))
+        .reduce(|nearest, next| if next.1 < nearest.1 { next } else { nearest })
+        .unwrap();
+
+    if distance > 0.0 {
+        return;
+    }
+
+    let radians = (90.0 - sprite.direction).to_radians();
+    let (mut dx, mut dy) = (radians.cos(), -radians.sin());
+    match edge {
+        Edge::Left => dx = dx.abs().max(0.2),
+        Edge::Top => dy = dy.abs().max(0.2),
+        Edge::Right => dx = -dx.abs().max(0.2),
+        Edge::Bottom => dy = -dy.abs().max(0.2),
+    }
+
+    let target = ctx.target_mut();
+    let sprite = target.sprite_mut().unwrap();
+    sprite.set_direction(dy.atan2(dx).to_degrees() + 90.0);
+
+    // Push the turned sprite fully back onto the stage so it doesn't bounce again next time
+    let sprite = target.sprite().unwrap();
+    let (x, y) = sprite.position_on_stage(target.costume(), sprite.x, sprite.y);
+    target.set_xy(x, y);
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Edge {
+    Left,
+    Top,
+    Right,
+    Bottom,
+}
+
+/// Compiles a glide as a loop that calls the block's runtime logic once per frame until
+/// it reports that the glide is done. The state of the glide is kept in locals between
+/// frames, which `set_end` initializes with the glide's destination.
+fn compile_glide(
+    mut ctx: CompileContext,
+    set_end: impl FnOnce(&mut ScriptCompiler, &Block, LocalHandle, LocalHandle),
+) {
+    let locals: [LocalHandle; 6] = std::array::from_fn(|_| ctx.compiler.claim_local());
+    let [secs, end_x, end_y, start_x, start_y, started_at] = locals;
+
+    ctx.compiler.build_set_local(secs, &ctx.block.inputs["SECS"]);
+    set_end(ctx.compiler, ctx.block, end_x, end_y);
+    ctx.compiler.build_set_local(started_at, -1.0);
+
+    let loop_start = ctx.compiler.label_here();
+    let loop_end = PlaceholderLabel::new();
+
+    for local in locals {
+        ctx.compiler.build_push(local);
+    }
+    ctx.build_call_self();
+
+    // The runtime logic pushes whether the glide is done, followed by its new state
+    for local in [started_at, start_y, start_x, end_y, end_x] {
+        ctx.compiler.write_op(Opcode::SetLocal);
+        ctx.compiler.write_imm(local.into());
+    }
+
+    ctx.compiler.build_jump_if(true, &loop_end);
+    ctx.compiler.build_loop_end(loop_start);
+
+    ctx.compiler.commit_placeholder(loop_end);
+    for local in locals {
+        ctx.compiler.release_local(local);
+    }
+}
+
+/// Runs a frame of a glide compiled by [`compile_glide`]. On the first frame,
+/// `resolve_end` turns the values the glide was started with into its destination.
+fn glide_step(
+    mut ctx: RuntimeContext,
+    resolve_end: impl FnOnce(&mut RuntimeContext, Value, Value) -> Option<(f64, f64)>,
+) {
+    let [secs, end_x, end_y, start_x, start_y, started_at] = ctx.task_mut().pop_values();
+    let duration = secs.cast_number();
+    let now = ctx.program().now().as_secs_f64();
+
+    let started_at = started_at.cast_number();
+    if started_at < 0.0 {
+        // Like Scratch, the sprite doesn't move until the frame after the glide starts.
+        let end = resolve_end(&mut ctx, end_x, end_y);
+        let (Some(end), Some(start)) = (end, sprite_position(&ctx)) else {
+            return push_glide_state(&mut ctx, true, (0.0, 0.0), (0.0, 0.0), now);
+        };
+
+        if duration <= 0.0 {
+            ctx.target_mut().set_xy(end.0, end.1);
+            return push_glide_state(&mut ctx, true, end, start, now);
+        }
+
+        return push_glide_state(&mut ctx, false, end, start, now);
+    }
+
+    let end = (end_x.cast_number(), end_y.cast_number());
+    let start = (start_x.cast_number(), start_y.cast_number());
+
+    let elapsed = now - started_at;
+    if elapsed < duration {
+        let frac = elapsed / duration;
+        let x = start.0 + frac * (end.0 - start.0);
+        let y = start.1 + frac * (end.1 - start.1);
+        ctx.target_mut().set_xy(x, y);
+        push_glide_state(&mut ctx, false, end, start, started_at);
+    } else {
+        ctx.target_mut().set_xy(end.0, end.1);
+        push_glide_state(&mut ctx, true, end, start, started_at);
+    }
+}
+
+fn push_glide_state(
+    ctx: &mut RuntimeContext,
+    done: bool,
+    end: (f64, f64),
+    start: (f64, f64),
+    started_at: f64,
+) {
+    let task = ctx.task_mut();
+    task.push(Value::Boolean(done));
+    for value in [end.0, end.1, start.0, start.1, started_at] {
+        task.push(Value::Number(value));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    blocks::BlockLibrary,
+    interpreter::{
+        opcode::{MathOp, Opcode},
+        value::Value,
+    },
+};
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    register_binary(library, "operator_add", ["NUM1", "NUM2"], Opcode::Add);
+    register_binary(library, "operator_subtract", ["NUM1", "NUM2"], Opcode::Subtract);
+    register_binary(library, "operator_multiply", ["NUM1", "NUM2"], Opcode::Multiply);
+    register_binary(library, "operator_divide", ["NUM1", "NUM2"], Opcode::Divide);
+    register_binary(library, "operator_mod", ["NUM1", "NUM2"], Opcode::Modulo);
+
+    register_comparison(library, "operator_lt", Ordering::Less);
+    register_comparison(library, "operator_equals", Ordering::Equal);
+    register_comparison(library, "operator_gt", Ordering::Greater);
+
+    library
+        .register_reporter("operator_and")
+        .compile_logic(|ctx| {
+            ctx.compiler.build_push_boolean(ctx.block.inputs.get("OPERAND1"));
+            ctx.compiler.build_push_boolean(ctx.block.inputs.get("OPERAND2"));
+            ctx.compiler.write_op(Opcode::And);
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_or")
+        .compile_logic(|ctx| {
+            ctx.compiler.build_push_boolean(ctx.block.inputs.get("OPERAND1"));
+            ctx.compiler.build_push_boolean(ctx.block.inputs.get("OPERAND2"));
+            ctx.compiler.write_op(Opcode::Or);
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_not")
+        .compile_logic(|ctx| {
+            ctx.compiler.build_push_boolean(ctx.block.inputs.get("OPERAND"));
+            ctx.compiler.write_op(Opcode::Not);
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_round")
+        .compile_logic(|ctx| {
+            ctx.compiler.build_push(&ctx.block.inputs["NUM"]);
+            ctx.compiler.write_op(Opcode::Round);
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_mathop")
+        .compile_logic(|ctx| {
+            let operator = ctx.block.simple_field("OPERATOR");
+            // Scratch reports 0 for operators it doesn't know
+            let Some(op) = MathOp::from_name(&operator) else {
+                ctx.compiler.build_push(0.0);
+                return;
+            };
+
+            ctx.compiler.build_push(&ctx.block.inputs["NUM"]);
+            ctx.compiler.write_op(Opcode::MathOp);
+            ctx.compiler.write_imm(op.into());
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_random")
+        .inputs_order(["FROM".into(), "TO".into()])
+        .runtime_logic(|mut ctx| {
+            let [from, to] = ctx.task_mut().pop_values();
+            let [n_from, n_to] = [from.cast_number(), to.cast_number()];
+
+            let low = n_from.min(n_to);
+            let high = n_from.max(n_to);
+
+            let rng = ctx.program_mut().rng_mut();
+            let result = if low == high {
+                low
+            } else if from.is_integer_like() && to.is_integer_like() {
+                low + (rng.next_f64() * (high + 1.0 - low)).floor()
+            } else {
+                rng.next_f64() * (high - low) + low
+            };
+
+            ctx.task_mut().push(Value::Number(result));
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_join")
+        .inputs_order(["STRING1".into(), "STRING2".into()])
+        .runtime_logic(|mut ctx| {
+            let [str1, str2] = ctx.task_mut().pop_strings();
+
+            let joined = format!("{str1}{str2}");
+            ctx.task_mut().push(Value::String(joined.into()));
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_letter_of")
+        .inputs_order(["LETTER".into(), "STRING".into()])
+        .runtime_logic(|mut ctx| {
+            let [index, string] = ctx.task_mut().pop_values();
+
+            // Scratch strings are indexed by UTF-16 code unit, like in JavaScript.
+            let index = index.cast_number() - 1.0;
+            let units = string.cast_string().encode_utf16().collect::<Vec<_>>();
+
+            let letter = if index < 0.0 || index >= units.len() as f64 {
+                Arc::from("")
+            } else {
+                let idx = index as usize;
+                String::from_utf16_lossy(&units[idx..idx + 1]).into()
+            };
+
+            ctx.task_mut().push(Value::String(letter));
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_length")
+        .inputs_order(["STRING".into()])
+        .runtime_logic(|mut ctx| {
+            let [string] = ctx.task_mut().pop_strings();
+
+            let length = string.encode_utf16().count();
+            ctx.task_mut().push(Value::Number(length as f64));
+        })
+        .finish();
+
+    library
+        .register_reporter("operator_contains")
+        .inputs_order(["STRING1".into(), "STRING2".into()])
+        .runtime_logic(|mut ctx| {
+            let [haystack, needle] = ctx.task_mut().pop_strings();
+
+            let contains = haystack.to_lowercase().contains(&needle.to_lowercase());
+            ctx.task_mut().push(Value::Boolean(contains));
+        })
+        .finish();
+}
+
+fn register_binary(
+    library: &mut BlockLibrary,
+    opcode: &str,
+    [left, right]: [&'static str; 2],
+    op: Opcode,
+) {
+    library
+        .register_reporter(opcode)
+        .compile_logic(move |ctx| {
+            ctx.compiler.build_push(&ctx.block.inputs[left]);
+            ctx.compiler.build_push(&ctx.block.inputs[right]);
+            ctx.compiler.write_op(op);
+        })
+        .finish();
+}
+
+fn register_comparison(library: &mut BlockLibrary, opcode: &str, cmp: Ordering) {
+    library
+        .register_reporter(opcode)
+        .compile_logic(move |ctx| {
+            let left = &ctx.block.inputs["OPERAND1"];
+            let right = &ctx.block.inputs["OPERAND2"];
+
+            ctx.compiler.build_cmp(left, cmp, right);
+        })
+        .finish();
+}
@@ -0,0 +1,150 @@
+use crate::{ast::Block, blocks::BlockLibrary, interpreter::value::Value};
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_block("procedures_call")
+        .compile_logic(|ctx| {
+            let proc_code = ctx
+                .block
+                .proc_code
+                .as_deref()
+                .expect("procedure call missing proc code");
+
+            // Calls to blocks that aren't defined do nothing in Scratch.
+            if let Some(procedure) = ctx.compiler.target.procedure(proc_code).cloned() {
+                ctx.compiler.build_call_procedure(&procedure, ctx.block);
+            }
+        })
+        .finish();
+
+    // Argument reporters used outside of their definition report 0 (or false), which
+    // is also what we push when we can't find the parameter.
+    for opcode in [Block::STRING_ARG, Block::BOOL_ARG] {
+        library
+            .register_reporter(opcode)
+            .compile_logic(|mut ctx| {
+                let name = ctx.block.simple_field(Block::ARG_NAME_FIELD);
+
+                match ctx.compiler.param(&name) {
+                    Some(local) => ctx.compiler.build_push(local),
+                    // There's no opcode for pushing a boolean, so only these get to runtime.
+                    None if &*ctx.block.opcode == Block::BOOL_ARG => ctx.build_call_self(),
+                    None => ctx.compiler.build_push(0.0),
+                }
+            })
+            .runtime_logic(|mut ctx| ctx.task_mut().push(Value::Boolean(false)))
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::project::ScratchProject,
+        interpreter::{Program, clock::VirtualClock, value::Value},
+        sb3::Sb3Project,
+    };
+
+    /// Defines `f %s %b`, which copies its arguments into `s` and `b`, and calls it without
+    /// filling in either argument.
+    const PROJECT: &str = r#"{"targets": [
+        {"isStage": true, "name": "Stage", "variables": {"vs": ["s", 0], "vb": ["b", 0]},
+            "lists": {}, "broadcasts": {}, "blocks": {}},
+        {"isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "broadcasts": {},
+            "blocks": {
+                "d0": {"opcode": "procedures_definition", "next": "d1", "parent": null,
+                    "inputs": {"custom_block": [1, "proto"]}, "fields": {}, "topLevel": true},
+                "proto": {"opcode": "procedures_prototype", "next": null, "parent": "d0",
+                    "inputs": {}, "fields": {}, "shadow": true, "topLevel": false,
+                    "mutation": {"tagName": "mutation", "children": [], "proccode": "f %s %b",
+                        "argumentids": "[\"as\", \"ab\"]",
+                        "argumentnames": "[\"text\", \"flag\"]",
+                        "argumentdefaults": "[\"\", false]", "warp": "false"}},
+                "d1": {"opcode": "data_setvariableto", "next": "d2", "parent": "d0",
+                    "inputs": {"VALUE": [3, "join", [10, ""]]}, "fields": {"VARIABLE": ["s", "vs"]},
+                    "topLevel": false},
+                "join": {"opcode": "operator_join", "next": null, "parent": "d1",
+                    "inputs": {"STRING1": [3, "text", [10, ""]], "STRING2": [1, [10, "|"]]},
+                    "fields": {}, "topLevel": false},
+                "text": {"opcode": "argument_reporter_string_number", "next": null,
+                    "parent": "join", "inputs": {}, "fields": {"VALUE": ["text", null]},
+                    "topLevel": false},
+                "d2": {"opcode": "data_setvariableto", "next": null, "parent": "d1",
+                    "inputs": {"VALUE": [3, "flag", [10, ""]]}, "fields": {"VARIABLE": ["b", "vb"]},
+                    "topLevel": false},
+                "flag": {"opcode": "argument_reporter_boolean", "next": null, "parent": "d2",
+                    "inputs": {}, "fields": {"VALUE": ["flag", null]}, "topLevel": false},
+                "c0": {"opcode": "event_whenflagclicked", "next": "c1", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+                "c1": {"opcode": "procedures_call", "next": null, "parent": "c0", "inputs": {},
+                    "fields": {}, "topLevel": false,
+                    "mutation": {"tagName": "mutation", "children": [], "proccode": "f %s %b",
+                        "argumentids": "[\"as\", \"ab\"]", "warp": "false"}}
+            }}
+    ]}"#;
+
+    /// Copies an argument reporter of each kind into `s` and `b` outside of any definition.
+    const OUTSIDE_DEFINITION: &str = r#"{"targets": [
+        {"isStage": true, "name": "Stage", "variables": {"vs": ["s", ""], "vb": ["b", ""]},
+            "lists": {}, "broadcasts": {}, "blocks": {}},
+        {"isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "broadcasts": {},
+            "blocks": {
+                "c0": {"opcode": "event_whenflagclicked", "next": "c1", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+                "c1": {"opcode": "data_setvariableto", "next": "c2", "parent": "c0",
+                    "inputs": {"VALUE": [3, "text", [10, ""]]}, "fields": {"VARIABLE": ["s", "vs"]},
+                    "topLevel": false},
+                "text": {"opcode": "argument_reporter_string_number", "next": null,
+                    "parent": "c1", "inputs": {}, "fields": {"VALUE": ["text", null]},
+                    "topLevel": false},
+                "c2": {"opcode": "data_setvariableto", "next": null, "parent": "c1",
+                    "inputs": {"VALUE": [3, "flag", [10, ""]]}, "fields": {"VARIABLE": ["b", "vb"]},
+                    "topLevel": false},
+                "flag": {"opcode": "argument_reporter_boolean", "next": null, "parent": "c2",
+                    "inputs": {}, "fields": {"VALUE": ["flag", null]}, "topLevel": false}
+            }}
+    ]}"#;
+
+    fn run(json: &str) -> Program {
+        let sb3: Sb3Project = serde_json::from_str(json).unwrap();
+        let mut program = ScratchProject::try_from(sb3).unwrap().compile();
+        program.set_clock(VirtualClock::new());
+        program.click_green_flag();
+        program.run_frame();
+        program
+    }
+
+    #[test]
+    fn missing_arguments_use_defaults() {
+        let program = run(PROJECT);
+
+        let stage = program.stage_id();
+        assert_eq!(program.variable_by_name(stage, "s"), Some(Value::from("|")));
+        assert_eq!(
+            program.variable_by_name(stage, "b"),
+            Some(Value::from("false"))
+        );
+    }
+
+    #[test]
+    fn string_argument_outside_definition() {
+        let program = run(OUTSIDE_DEFINITION);
+
+        let stage = program.stage_id();
+        assert_eq!(
+            program.variable_by_name(stage, "s"),
+            Some(Value::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn boolean_argument_outside_definition() {
+        let program = run(OUTSIDE_DEFINITION);
+
+        let stage = program.stage_id();
+        assert_eq!(
+            program.variable_by_name(stage, "b"),
+            Some(Value::Boolean(false))
+        );
+    }
+}
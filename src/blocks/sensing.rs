@@ -0,0 +1,213 @@
+use crate::{
+    ast::Primitive,
+    blocks::BlockLibrary,
+    codegen::PlaceholderLabel,
+    interpreter::{RuntimeContext, clock::CivilTime, value::Value},
+};
+
+/// Milliseconds from the Unix epoch to the start of 2000, in UTC.
+const MILLIS_TO_2000: f64 = 946_684_800_000.0;
+const MILLIS_PER_DAY: f64 = 86_400_000.0;
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_reporter("sensing_timer")
+        .runtime_logic(|mut ctx| {
+            let timer = ctx.program().timer();
+            ctx.task_mut().push(Value::Number(timer));
+        })
+        .finish();
+
+    library
+        .register_block("sensing_resettimer")
+        .runtime_logic(|mut ctx| ctx.program_mut().reset_timer())
+        .finish();
+
+    library
+        .register_block("sensing_askandwait")
+        .compile_logic(|mut ctx| {
+            // The question is only evaluated once, then it's checked on every frame until
+            // it's been answered
+            let question = ctx.compiler.claim_local();
+            ctx.compiler.build_set_local(question, &ctx.block.inputs["QUESTION"]);
+
+            let loop_start = ctx.compiler.label_here();
+            let loop_end = PlaceholderLabel::new();
+
+            ctx.compiler.build_push(question);
+            ctx.build_call_self();
+            ctx.compiler.build_jump_if(true, &loop_end);
+            ctx.compiler.build_loop_end(loop_start);
+
+            ctx.compiler.commit_placeholder(loop_end);
+            ctx.compiler.release_local(question);
+        })
+        .runtime_logic(|mut ctx| {
+            let [question] = ctx.task_mut().pop_strings();
+            let task_id = ctx.task().id();
+            let target_id = ctx.target_id();
+
+            let answered = ctx.program_mut().ask(task_id, target_id, question);
+            ctx.task_mut().push(Value::Boolean(answered));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_answer")
+        .runtime_logic(|mut ctx| {
+            let answer = ctx.program().answer().clone();
+            ctx.task_mut().push(Value::String(answer));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_keypressed")
+        .runtime_logic(|mut ctx| {
+            let key = ctx.task_mut().pop();
+            let pressed = ctx.program().input().is_key_pressed(&key);
+            ctx.task_mut().push(Value::Boolean(pressed));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_mousedown")
+        .runtime_logic(|mut ctx| {
+            let down = ctx.program().input().is_mouse_down();
+            ctx.task_mut().push(Value::Boolean(down));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_mousex")
+        .runtime_logic(|mut ctx| {
+            let (x, _) = ctx.program().mouse_position();
+            ctx.task_mut().push(Value::Number(x));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_mousey")
+        .runtime_logic(|mut ctx| {
+            let (_, y) = ctx.program().mouse_position();
+            ctx.task_mut().push(Value::Number(y));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_current")
+        .compile_logic(|mut ctx| {
+            let current = ctx.block.simple_field("CURRENTMENU");
+
+            ctx.compiler.build_push(Primitive::Text(current));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [current] = ctx.task_mut().pop_strings();
+            // Unlike Scratch, which reports the local time, this reports the time in UTC so
+            // that projects behave the same wherever they're run
+            let time = CivilTime::from_unix_time(ctx.program().unix_time());
+
+            let value = match &*current.to_lowercase() {
+                "year" => time.year as f64,
+                "month" => time.month.into(),
+                "date" => time.day.into(),
+                "dayofweek" => time.weekday.into(),
+                "hour" => time.hour.into(),
+                "minute" => time.minute.into(),
+                "second" => time.second.into(),
+                _ => 0.0,
+            };
+            ctx.task_mut().push(Value::Number(value));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_dayssince2000")
+        .runtime_logic(|mut ctx| {
+            let millis = ctx.program().unix_time().as_millis() as f64;
+            let days = (millis - MILLIS_TO_2000) / MILLIS_PER_DAY;
+            ctx.task_mut().push(Value::Number(days));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_username")
+        .runtime_logic(|mut ctx| {
+            let username = ctx.program().username().into();
+            ctx.task_mut().push(Value::String(username));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_loudness")
+        .runtime_logic(|mut ctx| {
+            // Like Scratch without a microphone
+            ctx.task_mut().push(Value::Number(-1.0));
+        })
+        .finish();
+
+    library
+        .register_reporter("sensing_of")
+        .compile_logic(|mut ctx| {
+            let property = ctx.block.simple_field("PROPERTY");
+
+            ctx.compiler.build_push(&ctx.block.inputs["OBJECT"]);
+            ctx.compiler.build_push(Primitive::Text(property));
+            ctx.build_call_self();
+        })
+        .runtime_logic(|mut ctx| {
+            let [object, property] = ctx.task_mut().pop_strings();
+            let value = property_of(&ctx, &object, &property).unwrap_or(Value::Number(0.0));
+            ctx.task_mut().push(value);
+        })
+        .finish();
+
+    // Menus report the value of their field, which is a key or target name, or a special
+    // value like `_stage_`.
+    for (opcode, field) in [
+        ("sensing_keyoptions", "KEY_OPTION"),
+        ("sensing_of_object_menu", "OBJECT"),
+    ] {
+        library
+            .register_reporter(opcode)
+            .compile_logic(move |ctx| {
+                let value = ctx.block.simple_field(field);
+                ctx.compiler.build_push(Primitive::Text(value));
+            })
+            .finish();
+    }
+}
+
+/// Reads a property of the stage or of a sprite other than a clone, or one of its
+/// variables. Returns `None` if the target or variable doesn't exist.
+fn property_of(ctx: &RuntimeContext, object: &str, property: &str) -> Option<Value> {
+    let program = ctx.program();
+    let target_id = match object {
+        "_stage_" => program.stage_id(),
+        name => program.find_target(name)?,
+    };
+    let target = program.target(target_id);
+    let costume_name = || Value::String(target.costume().map_or("".into(), |c| c.name.clone()));
+    let costume_number = || Value::Number(target.current_costume() as f64 + 1.0);
+
+    let Some(sprite) = target.sprite() else {
+        return match property {
+            "background #" | "backdrop #" => Some(costume_number()),
+            "backdrop name" => Some(costume_name()),
+            // Volume isn't modelled, so it's always the default
+            "volume" => Some(Value::Number(100.0)),
+            _ => program.variable_by_name(target_id, property),
+        };
+    };
+
+    match property {
+        "x position" => Some(Value::Number(sprite.x)),
+        "y position" => Some(Value::Number(sprite.y)),
+        "direction" => Some(Value::Number(sprite.direction)),
+        "costume #" => Some(costume_number()),
+        "costume name" => Some(costume_name()),
+        "size" => Some(Value::Number(sprite.size)),
+        "volume" => Some(Value::Number(100.0)),
+        _ => program.variable_by_name(target_id, property),
+    }
+}
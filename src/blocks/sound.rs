@@ -0,0 +1,61 @@
+use crate::{
+    ast::Primitive,
+    blocks::BlockLibrary,
+    interpreter::{opcode::Opcode, sprite::wrap_clamp, value::Value, RuntimeContext},
+};
+
+pub(super) fn register(library: &mut BlockLibrary) {
+    library
+        .register_block("sound_play")
+        .runtime_logic(|mut ctx| {
+            let sound = ctx.task_mut().pop();
+            play_sound(&mut ctx, &sound);
+        })
+        .finish();
+
+    library
+        .register_block("sound_playuntildone")
+        .compile_logic(|mut ctx| {
+            // The runtime logic reports how long the sound lasts, which we then wait for
+            ctx.compiler.build_push(&ctx.block.inputs["SOUND_MENU"]);
+            ctx.build_call_self();
+            ctx.compiler.write_op(Opcode::Sleep);
+        })
+        .runtime_logic(|mut ctx| {
+            let sound = ctx.task_mut().pop();
+            let duration = play_sound(&mut ctx, &sound).unwrap_or_default();
+            ctx.task_mut().push(Value::Number(duration));
+        })
+        .finish();
+
+    library
+        .register_reporter("sound_sounds_menu")
+        .compile_logic(|ctx| {
+            let value = ctx.block.simple_field("SOUND_MENU");
+            ctx.compiler.build_push(Primitive::Text(value));
+        })
+        .finish();
+}
+
+/// Starts playing one of the current target's sounds, by name or by 1-based number, and
+/// returns how many seconds it lasts.
+fn play_sound(ctx: &mut RuntimeContext, sound: &Value) -> Option<f64> {
+    let sounds = ctx.target().sounds();
+    let max = sounds.len().checked_sub(1)?;
+
+    let name = sound.cast_string();
+    let idx = match sounds.iter().position(|sound| sound.name == name) {
+        Some(idx) => idx,
+        None => {
+            let number = sound.try_as_number()?.trunc();
+            wrap_clamp(number - 1.0, 0.0, max as f64) as usize
+        }
+    };
+
+    let sound = sounds[idx].clone();
+    let target_id = ctx.target_id();
+    ctx.program_mut()
+        .notify(|observer| observer.sound_played(target_id, &sound));
+
+    Some(sound.sample_count as f64 / sound.rate as f64)
+}
@@ -5,9 +5,19 @@ use derive_more::{From, Into};
 use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    ast::{Block, Field, Input, Primitive, Script, Variable, VariableRef},
+    ast::{
+        Block, Event, Field, Input, List, ListRef, Primitive, ProcedureArgument, Script, Variable,
+        VariableRef,
+    },
     blocks::{BlockCompileLogic, BlockTypeLibrary},
-    interpreter::{self, opcode::Opcode, value::{Local, Value}, RuntimeContext},
+    interpreter::{
+        self,
+        id::Id,
+        opcode::Opcode,
+        source_map::{BlockSource, SourceMap},
+        value::{EventValue, Local, ProcedureValue, Value},
+        RuntimeContext,
+    },
 };
 
 #[derive(Clone)]
@@ -21,6 +31,8 @@ pub struct BlockType {
 
 impl BlockType {
     pub fn compile(&self, compiler: &mut ScriptCompiler, block: &Block) {
+        let start = compiler.data.len();
+
         if let Some(compile_logic) = &self.compile_logic {
             compile_logic(CompileContext {
                 compiler,
@@ -30,6 +42,14 @@ impl BlockType {
         } else {
             compiler.compile_runtime_only(block, self.id, &self.inputs_order);
         }
+
+        if let Some(id) = &block.id {
+            let source = BlockSource {
+                id: id.clone(),
+                opcode: block.opcode.clone(),
+            };
+            compiler.source_map.insert(start..compiler.data.len(), source);
+        }
     }
 
     pub fn name(&self) -> Arc<str> {
@@ -59,25 +79,30 @@ pub struct ScriptCompiler {
     pub target: Arc<TargetCodegenContext>,
     pub block_library: Arc<BlockTypeLibrary>,
     pub data: Vec<u32>,
+    /// Where each block's bytecode ended up in `data`.
+    pub source_map: SourceMap,
     pub suppress_yields: bool,
-    num_proc_params: usize,
+    proc_params: Vec<Arc<str>>,
     locals: Vec<Option<()>>,
 }
 
 impl ScriptCompiler {
+    /// Creates a compiler for a single script. If the script is a procedure definition,
+    /// `proc_params` are the names of its parameters, which occupy the first locals.
     pub fn new(
         target: Arc<TargetCodegenContext>,
         blocks: Arc<BlockTypeLibrary>,
         suppress_yields: bool,
-        num_proc_params: usize,
+        proc_params: Vec<Arc<str>>,
     ) -> Self {
         Self {
             target,
             block_library: blocks,
             data: vec![],
+            source_map: SourceMap::new(),
             suppress_yields,
-            num_proc_params,
-            locals: vec![None; num_proc_params],
+            locals: vec![None; proc_params.len()],
+            proc_params,
         }
     }
 
@@ -90,11 +115,7 @@ impl ScriptCompiler {
         for block in substack {
             self.compile_block(block);
         }
-
-        if substack.is_empty() {
-            self.build_yield();
-        }
-}
+    }
 
     pub fn compile_block(&mut self, block: &Block) {
         let Some(handler) = self.block_library.block(&block.opcode) else {
@@ -124,6 +145,15 @@ impl ScriptCompiler {
         self.locals[handle.0 as usize] = Some(());
     }
 
+    /// Finds the local holding the procedure parameter with the given name, if this
+    /// script is a procedure that has one.
+    pub fn param(&self, name: &str) -> Option<LocalHandle> {
+        self.proc_params
+            .iter()
+            .position(|param| &**param == name)
+            .map(|idx| LocalHandle(idx as u32))
+    }
+
     fn compile_runtime_only(&mut self, block: &Block, runtime_id: u32, inputs_order: &[Arc<str>]) {
         // Assume runtime-only implementation. Fields aren't be represented
         // on the stack, so we disallow them.
@@ -182,6 +212,39 @@ impl ScriptCompiler {
         destination.write(self);
     }
 
+    /// Jumps to the destination if the given boolean input evaluates to `condition`. Empty
+    /// boolean slots are treated as false, like in Scratch.
+    pub fn build_jump_if_input(
+        &mut self,
+        input: Option<&Input>,
+        condition: bool,
+        destination: &impl Label,
+    ) {
+        match input {
+            Some(input) if !input.blocks.is_empty() => {
+                self.build_push(input);
+                self.build_jump_if(condition, destination);
+            }
+            _ if !condition => self.build_jump(destination),
+            _ => {}
+        }
+    }
+
+    /// Pushes a boolean input to the stack. Empty boolean slots are pushed as a falsy value.
+    pub fn build_push_boolean(&mut self, input: Option<&Input>) {
+        match input {
+            Some(input) if !input.blocks.is_empty() => self.build_push(input),
+            _ => self.build_push(0.0),
+        }
+    }
+
+    /// Ends an iteration of a loop by yielding (so the loop runs at most once per frame)
+    /// and then jumping back to the start of the loop.
+    pub fn build_loop_end(&mut self, loop_start: ConcreteLabel) {
+        self.build_yield();
+        self.build_jump(loop_start);
+    }
+
     pub fn build_set_var(&mut self, variable: VariableRef, value: impl StackRepresentable) {
         let handle = self.target.var(variable);
 
@@ -198,6 +261,29 @@ impl ScriptCompiler {
         self.write_imm(handle.into());
     }
 
+    /// Writes an opcode that operates on a list, followed by the list's handle as its
+    /// immediate. Any inputs to the operation should be pushed beforehand.
+    pub fn build_list_op(&mut self, opcode: Opcode, list: ListRef) {
+        let handle = self.target.list(list);
+
+        self.write_op(opcode);
+        self.write_imm(handle.into());
+    }
+
+    /// Calls a procedure, passing its arguments in order. Arguments missing from the
+    /// call site are passed as the default from the procedure's prototype.
+    pub fn build_call_procedure(&mut self, procedure: &ProcedureSignature, call: &Block) {
+        for arg in &procedure.arguments {
+            match call.inputs.get(&arg.id) {
+                Some(input) if !input.blocks.is_empty() => self.build_push(input),
+                _ => self.build_push(Primitive::Text(arg.default.clone())),
+            }
+        }
+
+        self.write_op(Opcode::CallProcedure);
+        self.write_imm(procedure.id.get() as u32);
+    }
+
     pub fn build_set_local(&mut self, local_handle: LocalHandle, value: impl StackRepresentable) {
         self.build_push(value);
         self.write_op(Opcode::SetLocal);
@@ -208,9 +294,9 @@ impl ScriptCompiler {
         self.build_push(left);
         self.build_push(right);
         self.write_op(match cmp {
-            Ordering::Equal => unimplemented!(),
+            Ordering::Equal => Opcode::Equals,
             Ordering::Greater => Opcode::GreaterThan,
-            Ordering::Less => unimplemented!(),
+            Ordering::Less => Opcode::LessThan,
         });
     }
 
@@ -234,10 +320,10 @@ impl ScriptCompiler {
             .iter()
             .enumerate()
             .map(|(idx, _)| {
-                let name = if let Some(idx) = idx.checked_sub(self.num_proc_params) {
+                let name = if let Some(idx) = idx.checked_sub(self.proc_params.len()) {
                     format!("Auto-generated #{idx}")
                 } else {
-                    format!("Procedure param #{idx}")
+                    format!("Procedure param {:?}", self.proc_params[idx])
                 };
 
                 Local::new(Some(name.into()))
@@ -249,20 +335,38 @@ impl ScriptCompiler {
 #[derive(Debug, Clone)]
 pub struct ProjectContext {
     pub variables: IndexMap<Arc<str>, Variable>,
+    pub lists: IndexMap<Arc<str>, List>,
+    /// The ids of every broadcast in the project.
+    pub events: IndexSet<Arc<str>>,
     pub text_consts: Arc<IndexSet<Arc<str>>>,
 }
 
 impl ProjectContext {
     pub fn new(
         variables: impl IntoIterator<Item = Variable>,
+        lists: impl IntoIterator<Item = List>,
+        events: impl IntoIterator<Item = Arc<str>>,
         text_consts: Arc<IndexSet<Arc<str>>>,
     ) -> Self {
         Self {
             variables: IndexMap::from_iter(variables.into_iter().map(|var| (var.id(), var))),
+            lists: IndexMap::from_iter(lists.into_iter().map(|list| (list.id(), list))),
+            events: IndexSet::from_iter(events),
             text_consts,
         }
     }
 
+    /// Finds the id a broadcast will have at runtime. Events must be passed to the context
+    /// in the same order they're registered with the program.
+    pub fn event(&self, event: &Event) -> Id<EventValue> {
+        let idx = self
+            .events
+            .get_index_of(&event.id())
+            .expect("unknown event");
+
+        Id::from(idx)
+    }
+
     pub fn text(&self, value: Arc<str>) -> ConstantHandle {
         let idx = self
             .text_consts
@@ -276,22 +380,35 @@ impl ProjectContext {
 pub struct TargetCodegenContext {
     pub project: Arc<ProjectContext>,
     pub variables: IndexMap<Arc<str>, Variable>,
+    pub lists: IndexMap<Arc<str>, List>,
+    pub procedures: HashMap<Arc<str>, ProcedureSignature>,
 }
 
 impl TargetCodegenContext {
     pub fn new(
         project_ctx: Arc<ProjectContext>,
         sprite_vars: impl IntoIterator<Item = Variable>,
+        sprite_lists: impl IntoIterator<Item = List>,
+        procedures: HashMap<Arc<str>, ProcedureSignature>,
     ) -> Self {
         let mut vars_lookup_map = project_ctx.variables.clone();
         vars_lookup_map.extend(sprite_vars.into_iter().map(|var| (var.id(), var)));
 
+        let mut lists_lookup_map = project_ctx.lists.clone();
+        lists_lookup_map.extend(sprite_lists.into_iter().map(|list| (list.id(), list)));
+
         Self {
             variables: vars_lookup_map,
+            lists: lists_lookup_map,
+            procedures,
             project: project_ctx,
         }
     }
 
+    pub fn procedure(&self, proc_code: &str) -> Option<&ProcedureSignature> {
+        self.procedures.get(proc_code)
+    }
+
     pub fn var(&self, var: VariableRef) -> VarHandle {
         let idx = self
             .variables
@@ -301,6 +418,15 @@ impl TargetCodegenContext {
         VarHandle::from(idx as u32)
     }
 
+    pub fn list(&self, list: ListRef) -> ListHandle {
+        let idx = self
+            .lists
+            .get_index_of(&list.id())
+            .expect("unknown list");
+
+        ListHandle::from(idx as u32)
+    }
+
     pub fn text(&self, value: Arc<str>) -> ConstantHandle {
         self.project.text(value)
     }
@@ -310,6 +436,15 @@ impl TargetCodegenContext {
     }
 }
 
+/// What a script needs to know to call one of its target's custom blocks.
+#[derive(Debug, Clone)]
+pub struct ProcedureSignature {
+    /// The id the procedure will be registered with in the [`Program`](interpreter::Program).
+    pub id: Id<ProcedureValue>,
+    /// The procedure's arguments, in the order they're passed.
+    pub arguments: Vec<ProcedureArgument>,
+}
+
 pub trait StackRepresentable {
     fn build_push_to_stack(self, compiler: &mut ScriptCompiler);
 }
@@ -345,6 +480,7 @@ impl StackRepresentable for Primitive {
             Primitive::Integer(num) => compiler.build_push(num as f64),
             Primitive::WholeNumber(num) => compiler.build_push(num as f64),
             Primitive::Variable(var) => compiler.build_push(compiler.target.var(var)),
+            Primitive::List(list) => compiler.build_push(compiler.target.list(list)),
             Primitive::Event(_) => {
                 panic!("events cannot be pushed to the stack");
             }
@@ -383,6 +519,16 @@ impl StackRepresentable for VarHandle {
     }
 }
 
+#[derive(Debug, From, Into, Clone, Copy, PartialEq, Eq)]
+pub struct ListHandle(u32);
+
+impl StackRepresentable for ListHandle {
+    fn build_push_to_stack(self, compiler: &mut ScriptCompiler) {
+        compiler.write_op(Opcode::PushList);
+        compiler.write_imm(self.into());
+    }
+}
+
 #[derive(Debug, From, Into, Clone, Copy, PartialEq, Eq)]
 pub struct LocalHandle(u32);
 
@@ -406,6 +552,12 @@ impl ConcreteLabel {
     }
 }
 
+impl<L: Label> Label for &L {
+    fn write(&self, compiler: &mut ScriptCompiler) {
+        (*self).write(compiler);
+    }
+}
+
 impl Label for ConcreteLabel {
     fn write(&self, compiler: &mut ScriptCompiler) {
         compiler.write_imm(self.0 as u32);
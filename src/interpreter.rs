@@ -1,60 +1,163 @@
 use std::{
-    cmp::Reverse, collections::{hash_map::Entry, BinaryHeap, HashMap, VecDeque}, convert::identity, rc::Rc, sync::Arc, thread::sleep, time::{Duration, Instant}
+    cmp::Reverse, collections::{hash_map::Entry, BinaryHeap, HashMap, VecDeque}, convert::identity, mem, panic::{self, AssertUnwindSafe}, rc::Rc, sync::Arc, time::Duration
 };
 
 use itertools::Itertools;
 use num_enum::TryFromPrimitive;
-use owo_colors::OwoColorize;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    ast::Target,
+    ast::{Costume, Sound, Target},
     blocks::{BlockRuntimeLibrary, BlockRuntimeLogic},
     interpreter::{
+        clock::{Clock, RealClock},
         id::Id,
-        opcode::{BuiltinProcedure, Opcode, Trigger},
-        value::{EventValue, ProcedureValue, Value, VarState},
+        input::{AnswerSource, InputState, Question, scratch_key},
+        observer::ProgramObserver,
+        opcode::{BuiltinProcedure, MathOp, Opcode, Trigger},
+        replay::{HostInput, RecordedInput, Recording},
+        rng::Rng,
+        source_map::BlockSource,
+        sprite::{
+            wrap_clamp, Bubble, BubbleKind, GraphicEffects, SpriteState, STAGE_HEIGHT, STAGE_WIDTH,
+        },
+        trace::{TraceEvent, TraceLevel, TraceRecord, TraceSink},
+        value::{EventValue, ListIndex, ListState, ProcedureValue, Value, VarState},
     },
 };
 
+pub mod clock;
+pub mod disasm;
 pub mod id;
+pub mod image;
+pub mod input;
+pub mod observer;
 pub mod opcode;
+pub mod replay;
+pub mod rng;
+pub mod source_map;
+pub mod sprite;
+pub mod trace;
 pub mod value;
 
 #[derive(Debug)]
 pub struct Program {
     constants: Box<[Value]>,
     global_vars: Vec<VarState>,
+    global_lists: Vec<ListState>,
     procedures: Vec<Rc<ProcedureValue>>,
     builtins: Option<BlockRuntimeLibrary>,
     events: Vec<EventValue>,
     triggers: HashMap<Trigger, Vec<Rc<ProcedureValue>>>,
     targets: Vec<TargetScope>,
+    /// The ids of every target in the order they're drawn, from back to front. The stage
+    /// is always at the back.
+    layers: Vec<usize>,
+    /// Slots in `targets` left behind by deleted clones, which new clones reuse.
+    free_slots: Vec<usize>,
+    clone_limit: usize,
+    rng: Rng,
+    /// How long a task can run in warp mode before it's forced to yield for a redraw.
+    /// This is measured by the program's clock, so a virtual clock keeps runs reproducible.
+    warp_time: Duration,
+    clock: Box<dyn Clock>,
+    frame_rate: u32,
+    frame_count: u64,
+    next_frame: Duration,
+    timer_start: Duration,
+    input: InputState,
+    /// Questions asked by tasks, in the order they'll be shown. Only the first one is
+    /// shown and can be answered.
+    questions: VecDeque<Question>,
+    answer: Arc<str>,
+    answer_source: Option<Box<dyn AnswerSource>>,
+    username: Arc<str>,
+    /// The last value of each edge-activated hat's condition, by script and target.
+    edge_values: HashMap<(Id<ProcedureValue>, usize), bool>,
+    /// The ids given to the tasks that check edge-activated hats, by script and target.
+    /// Checks that don't get past their hat are never reported, so their ids are reused
+    /// until one does.
+    edge_task_ids: HashMap<(Id<ProcedureValue>, usize), Id<Task>>,
+    /// The recording of inputs given to the program, if one was started.
+    recording: Option<Recording>,
 
     /// A queue of tasks that must be scheduled before this frame is over.
     task_queue: VecDeque<Task>,
     /// A list of tasks that are inactive or waiting for the next frame.
     sleepers: BinaryHeap<Reverse<Sleeper>>,
+    current_task: Option<RunningTask>,
+    /// Whether the running task restarted its own script, so it must start over once it
+    /// yields.
+    restart_current: bool,
+    /// Whether the running task was stopped, so it must finish once it yields.
+    stop_current: bool,
+    /// Breaks ties between sleepers with the same wake time so they wake in the order
+    /// they went to sleep.
+    sleeper_count: u64,
+    next_task_id: usize,
+    observers: Vec<Box<dyn ProgramObserver>>,
+    /// Targets that have been modified since observers were last notified.
+    changed_targets: Vec<usize>,
+    trace_level: TraceLevel,
+    tracer: Option<Box<dyn TraceSink>>,
 }
 
 impl Program {
+    /// The warp time used by Scratch.
+    pub const DEFAULT_WARP_TIME: Duration = Duration::from_millis(500);
+    /// Scratch's standard frame rate.
+    pub const DEFAULT_FRAME_RATE: u32 = 30;
+    /// The frame rate Scratch uses when compatibility mode is turned off.
+    pub const HIGH_FRAME_RATE: u32 = 60;
+    /// The most clones Scratch allows to exist at once.
+    pub const DEFAULT_CLONE_LIMIT: usize = 300;
+
     pub fn new(
         builtins: BlockRuntimeLibrary,
         constants: Box<[Value]>,
         events: Vec<EventValue>,
         global_vars: Vec<VarState>,
+        global_lists: Vec<ListState>,
         targets: Vec<TargetScope>,
     ) -> Self {
         Self {
             constants,
             global_vars,
+            global_lists,
             procedures: Vec::new(),
             builtins: Some(builtins),
             events,
             triggers: HashMap::new(),
+            layers: (0..targets.len()).collect(),
             targets,
+            free_slots: Vec::new(),
+            clone_limit: Self::DEFAULT_CLONE_LIMIT,
+            rng: Rng::from_time(),
+            warp_time: Self::DEFAULT_WARP_TIME,
+            clock: Box::new(RealClock::new()),
+            frame_rate: Self::DEFAULT_FRAME_RATE,
+            frame_count: 0,
+            next_frame: Duration::ZERO,
+            timer_start: Duration::ZERO,
+            input: InputState::new(),
+            questions: VecDeque::new(),
+            answer: "".into(),
+            answer_source: None,
+            username: "".into(),
+            edge_values: HashMap::new(),
+            edge_task_ids: HashMap::new(),
+            recording: None,
             task_queue: VecDeque::new(),
             sleepers: BinaryHeap::new(),
+            current_task: None,
+            restart_current: false,
+            stop_current: false,
+            sleeper_count: 0,
+            next_task_id: 0,
+            observers: Vec::new(),
+            changed_targets: Vec::new(),
+            trace_level: TraceLevel::Off,
+            tracer: None,
         }
     }
 
@@ -84,17 +187,228 @@ impl Program {
         }
     }
 
-    pub fn dispatch(&mut self, trigger: Trigger) {
-        let handler_procedures = self
-            .triggers
-            .get(&trigger)
-            .map_or([].as_slice(), Vec::as_slice);
+    /// Starts the scripts with the given trigger and returns the ids of their tasks. Like
+    /// in Scratch, a script that's already running is restarted rather than started twice,
+    /// unless the trigger doesn't restart scripts, in which case it's left alone. Sprite
+    /// scripts are started for the original sprite and each of its clones.
+    pub fn dispatch(&mut self, trigger: Trigger) -> Vec<Id<Task>> {
+        self.start_scripts(trigger, None)
+    }
+
+    /// Like [`Program::dispatch`], but only starts scripts on a single target, such as a
+    /// clone or the sprite that was clicked.
+    pub fn dispatch_to(&mut self, trigger: Trigger, target_id: usize) -> Vec<Id<Task>> {
+        self.start_scripts(trigger, Some(target_id))
+    }
+
+    fn start_scripts(&mut self, trigger: Trigger, only: Option<usize>) -> Vec<Id<Task>> {
+        let handler_procedures = self.triggers.get(&trigger).cloned().unwrap_or_default();
+        let mut started = Vec::new();
+
+        for procedure in handler_procedures {
+            let instances = match only {
+                Some(target_id) if self.original_id(target_id) == procedure.target_id => {
+                    vec![target_id]
+                }
+                Some(_) => continue,
+                None => self.instances_of(procedure.target_id),
+            };
+
+            for target_id in instances {
+                if let Some(id) = self.find_task_running(&procedure, target_id) {
+                    if trigger.restarts_running() {
+                        self.restart_task(id);
+                        started.push(id);
+                    }
+                    continue;
+                }
+
+                let task = if trigger == Trigger::EdgeActivated {
+                    self.new_edge_task(procedure.clone(), target_id)
+                } else {
+                    self.new_task(procedure.clone(), target_id)
+                };
+                started.push(task.id());
+                self.enqueue(task);
+            }
+        }
+
+        started
+    }
+
+    /// Sends a broadcast, starting the scripts that receive it. Returns the ids of the
+    /// tasks that were started or restarted.
+    pub fn broadcast(&mut self, event: Id<EventValue>) -> Vec<Id<Task>> {
+        let name: Arc<str> = self.events[event.get()].name().into();
+        self.notify(|observer| observer.broadcast_sent(event, &name));
+        self.dispatch(Trigger::Event(event))
+    }
+
+    /// Finds a broadcast by name. Scratch ignores case when looking up broadcasts.
+    pub fn find_event(&self, name: &str) -> Option<Id<EventValue>> {
+        let name = name.to_lowercase();
+        self.events
+            .iter()
+            .position(|event| event.name().to_lowercase() == name)
+            .map(Id::from)
+    }
+
+    /// Whether a task is still running, either this frame or after waking up.
+    pub fn is_running(&self, id: Id<Task>) -> bool {
+        self.current_task.is_some_and(|current| current.id == id)
+            || self.task_queue.iter().any(|task| task.id == id)
+            || self.sleepers.iter().any(|Reverse(Sleeper(task, _))| task.id == id)
+    }
+
+    /// The tasks that are waiting to run, in no particular order. The task that's running
+    /// isn't included, so this is best called between frames.
+    pub fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.task_queue
+            .iter()
+            .chain(self.sleepers.iter().map(|Reverse(Sleeper(task, _))| task))
+    }
+
+    /// Stops a task. Returns whether the task was running.
+    pub fn kill_task(&mut self, id: Id<Task>) -> bool {
+        if self.current_task.is_some_and(|current| current.id == id) {
+            // The running task stops as soon as it yields
+            self.stop_current = true;
+            return true;
+        }
+
+        let task = if let Some(idx) = self.task_queue.iter().position(|task| task.id == id) {
+            self.task_queue.remove(idx)
+        } else {
+            let mut sleepers = mem::take(&mut self.sleepers).into_vec();
+            let task = sleepers
+                .iter()
+                .position(|Reverse(Sleeper(task, _))| task.id == id)
+                .map(|idx| sleepers.remove(idx).0.0);
+            self.sleepers = sleepers.into();
+            task
+        };
+
+        let Some(task) = task else {
+            return false;
+        };
+        if !task.checking_edge {
+            self.notify(|observer| observer.task_finished(&task));
+        }
+        true
+    }
+
+    /// Stops every task belonging to a target, except for the given task.
+    pub fn stop_other_tasks(&mut self, target_id: usize, except: Id<Task>) {
+        let ids = self
+            .tasks()
+            .filter(|task| task.target_id == target_id && task.id != except)
+            .map(|task| task.id)
+            .collect::<Vec<_>>();
 
-        let tasks = handler_procedures.iter().cloned().map(Task::new);
-        self.task_queue.extend(tasks);
+        for id in ids {
+            self.kill_task(id);
+        }
+
+        if let Some(current) = self.current_task
+            && current.id != except
+            && current.target_id == target_id
+        {
+            self.stop_current = true;
+        }
     }
 
+    /// Stops every task, deletes every clone and clears speech bubbles, like Scratch's
+    /// stop sign.
+    pub fn stop_all(&mut self) {
+        let ids = self.tasks().map(|task| task.id).collect::<Vec<_>>();
+        for id in ids {
+            self.kill_task(id);
+        }
+
+        if self.current_task.is_some() {
+            self.stop_current = true;
+        }
+
+        for target_id in self.clones().collect::<Vec<_>>() {
+            self.delete_clone(target_id);
+        }
+
+        // Bubbles are all cleared below, so the questions can simply be dropped
+        self.questions.clear();
+
+        for target_id in 0..self.targets.len() {
+            self.set_bubble(target_id, None);
+        }
+    }
+
+    /// Finds the task running a script on a target, if there is one.
+    fn find_task_running(&self, script: &ProcedureValue, target_id: usize) -> Option<Id<Task>> {
+        let script = script.id();
+
+        if let Some(current) = self.current_task
+            && current.script == script
+            && current.target_id == target_id
+        {
+            return Some(current.id);
+        }
+
+        self.tasks()
+            .find(|task| task.root.id() == script && task.target_id == target_id)
+            .map(|task| task.id)
+    }
+
+    /// Sends a task back to the start of its script. Sleeping tasks are woken up so that
+    /// they start over during this frame.
+    fn restart_task(&mut self, id: Id<Task>) {
+        if self.current_task.is_some_and(|current| current.id == id) {
+            // The running task can't be touched until it yields
+            self.restart_current = true;
+        } else if let Some(task) = self.task_queue.iter_mut().find(|task| task.id == id) {
+            task.restart();
+        } else {
+            let mut sleepers = mem::take(&mut self.sleepers).into_vec();
+            if let Some(idx) = sleepers.iter().position(|Reverse(Sleeper(task, _))| task.id == id) {
+                let Reverse(Sleeper(mut task, _)) = sleepers.remove(idx);
+                task.restart();
+                self.task_queue.push_back(task);
+            }
+            self.sleepers = sleepers.into();
+        }
+    }
+
+    /// Creates a task that runs the given procedure on a target, with an id that's unique
+    /// within this program. The target must be the procedure's target or one of its clones.
+    pub fn new_task(&mut self, procedure: Rc<ProcedureValue>, target_id: usize) -> Task {
+        debug_assert_eq!(self.original_id(target_id), procedure.target_id);
+        let id = self.next_task_id.into();
+        self.next_task_id += 1;
+        Task::new(id, procedure, target_id)
+    }
+
+    /// Creates a task that checks an edge-activated hat. It's kept from observers until
+    /// it gets past the hat, since these tasks are started on every frame.
+    fn new_edge_task(&mut self, procedure: Rc<ProcedureValue>, target_id: usize) -> Task {
+        let key = (procedure.id(), target_id);
+        let id = match self.edge_task_ids.get(&key) {
+            Some(&id) => id,
+            None => {
+                let id = self.next_task_id.into();
+                self.next_task_id += 1;
+                self.edge_task_ids.insert(key, id);
+                id
+            }
+        };
+
+        let mut task = Task::new(id, procedure, target_id);
+        task.checking_edge = true;
+        task
+    }
+
+    /// Schedules a new task to start running during the current or next frame.
     pub fn enqueue(&mut self, task: Task) {
+        if !task.checking_edge {
+            self.notify(|observer| observer.task_started(&task));
+        }
         self.task_queue.push_back(task);
     }
 
@@ -102,33 +416,45 @@ impl Program {
         !self.sleepers.is_empty() || !self.task_queue.is_empty()
     }
 
-    pub fn next_wake(&self) -> Instant {
-        self.sleepers
-            .peek()
-            .map_or_else(Instant::now, |s| s.0.0.wake_time)
+    pub fn next_wake(&self) -> Option<Duration> {
+        self.sleepers.peek().map(|s| s.0.0.wake_time)
     }
 
-    fn wake_sleepers(&mut self, now: Instant) {
-        while let Some(Reverse(Sleeper(sleeper))) = self.sleepers.peek()
+    fn wake_sleepers(&mut self, now: Duration) {
+        while let Some(Reverse(Sleeper(sleeper, _))) = self.sleepers.peek()
             && sleeper.wake_time <= now
         {
-            let Reverse(Sleeper(task)) = self.sleepers.pop().unwrap();
+            let Reverse(Sleeper(task, _)) = self.sleepers.pop().unwrap();
             self.enqueue(task);
         }
     }
 
-    /// Enqueues tasks that are done sleeping, then runs the interpreter
-    /// until all tasks are sleeping again. Tasks are sent to sleep whenever
+    fn sleep(&mut self, task: Task) {
+        self.sleepers.push(Reverse(Sleeper(task, self.sleeper_count)));
+        self.sleeper_count += 1;
+    }
+
+    /// Waits for the next frame to start, enqueues tasks that are done sleeping, then runs
+    /// the interpreter until all tasks are sleeping again. Tasks are sent to sleep whenever
     /// they yield or wait for a duration of time.
     pub fn run_frame(&mut self) {
-        let frame_start = Instant::now();
+        self.clock.wait_until(self.next_frame);
+        let frame_start = self.clock.now();
 
-        let wake_time = self.next_wake();
-        if let Some(delay) = wake_time.checked_duration_since(frame_start) {
-            sleep(delay);
-        }
+        // If we've fallen behind, start the next frame as soon as possible rather than
+        // trying to catch up.
+        self.next_frame = (self.next_frame + self.frame_duration()).max(frame_start);
+        self.frame_count += 1;
 
-        self.wake_sleepers(wake_time);
+        let frame = self.frame_count;
+        self.notify(|observer| observer.frame_started(frame));
+
+        self.expire_bubbles(frame_start);
+        self.wake_sleepers(frame_start);
+
+        // Like in Scratch, edge-activated hats are started on every frame so that they can
+        // check their conditions
+        self.dispatch(Trigger::EdgeActivated);
 
         let mut next_priority = frame_start;
 
@@ -139,12 +465,421 @@ impl Program {
             task.wake_time = next_priority;
             next_priority += Duration::from_nanos(1);
 
-            task.run_until_yield(self);
+            // Panics from the interpreter or a builtin are passed on, but observers are told
+            // which block was running first so that the failure can be found in the project.
+            self.current_task = Some(RunningTask {
+                id: task.id,
+                script: task.root.id(),
+                target_id: task.target_id,
+            });
+            let result = panic::catch_unwind(AssertUnwindSafe(|| task.run_until_yield(self)));
+            self.current_task = None;
+            if let Err(payload) = result {
+                let block = task.current_block().cloned();
+                self.notify(|observer| observer.task_panicked(&task, block.as_ref()));
+                panic::resume_unwind(payload);
+            }
 
-            if !task.is_complete() {
-                self.sleepers.push(Reverse(Sleeper(task)));
+            // Stopping wins over restarting, since a stopped task shouldn't run again
+            let restart = mem::take(&mut self.restart_current);
+            if mem::take(&mut self.stop_current) {
+                task.complete = true;
+            } else if restart {
+                task.restart();
+            }
+
+            if task.is_complete() {
+                if !task.checking_edge {
+                    self.notify(|observer| observer.task_finished(&task));
+                }
+            } else {
+                self.sleep(task);
             }
         }
+
+        if self.recording.is_some() {
+            let hash = replay::state_hash(self);
+            if let Some(recording) = &mut self.recording {
+                recording.frame_hashes.push(hash);
+            }
+        }
+
+        self.notify(|observer| observer.frame_finished(frame));
+    }
+
+    pub fn trace_level(&self) -> TraceLevel {
+        self.trace_level
+    }
+
+    /// Starts sending trace records with the given level of detail to a sink. Tracing is
+    /// off by default.
+    pub fn set_tracer(&mut self, level: TraceLevel, sink: impl TraceSink + 'static) {
+        self.trace_level = level;
+        self.tracer = Some(Box::new(sink));
+    }
+
+    fn trace(&mut self, task: Id<Task>, procedure: &ProcedureValue, pc: usize, event: TraceEvent) {
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+
+        tracer.record(&TraceRecord {
+            frame: self.frame_count,
+            task,
+            procedure: procedure.name(),
+            pc,
+            block: procedure.source_map().block_at(pc),
+            event,
+        });
+    }
+
+    /// Subscribes an observer to changes in the program's state.
+    pub fn add_observer(&mut self, observer: impl ProgramObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Calls a method on every observer.
+    pub fn notify(&mut self, mut event: impl FnMut(&mut dyn ProgramObserver)) {
+        for observer in &mut self.observers {
+            event(observer.as_mut());
+        }
+    }
+
+    /// Sets or clears the speech bubble of a sprite, notifying observers of the change.
+    pub fn set_bubble(&mut self, target_id: usize, bubble: Option<Bubble>) {
+        let Some(sprite) = self.targets[target_id].sprite_mut() else {
+            return;
+        };
+
+        if sprite.bubble.is_none() && bubble.is_none() {
+            return;
+        }
+
+        sprite.bubble = bubble;
+        let bubble = sprite.bubble.clone();
+        self.notify(|observer| observer.bubble_changed(target_id, bubble.as_ref()));
+    }
+
+    /// Clears timed speech bubbles that have been shown for long enough.
+    fn expire_bubbles(&mut self, now: Duration) {
+        for target_id in 0..self.targets.len() {
+            let expired = self.targets[target_id]
+                .sprite()
+                .and_then(|sprite| sprite.bubble.as_ref())
+                .and_then(|bubble| bubble.expires_at)
+                .is_some_and(|expires_at| expires_at <= now);
+
+            if expired {
+                self.set_bubble(target_id, None);
+            }
+        }
+    }
+
+    /// The current time according to the program's clock.
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        let now = clock.now();
+        self.clock = Box::new(clock);
+        self.next_frame = now;
+        self.timer_start = now;
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.frame_rate
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: u32) {
+        assert!(frame_rate > 0, "frame rate must be positive");
+        self.frame_rate = frame_rate;
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs(1) / self.frame_rate
+    }
+
+    /// The number of frames that have been run.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The value of Scratch's timer, in seconds.
+    pub fn timer(&self) -> f64 {
+        (self.now() - self.timer_start).as_secs_f64()
+    }
+
+    pub fn reset_timer(&mut self) {
+        self.timer_start = self.now();
+    }
+
+    /// The current date and time, as a duration since the Unix epoch.
+    pub fn unix_time(&self) -> Duration {
+        self.clock.unix_time()
+    }
+
+    /// The position of the mouse pointer in stage coordinates.
+    pub fn mouse_position(&self) -> (f64, f64) {
+        self.input.mouse_position()
+    }
+
+    pub fn set_mouse_position(&mut self, x: f64, y: f64) {
+        self.input.set_mouse_position(x, y);
+    }
+
+    /// The state of the mouse and keyboard.
+    pub fn input(&self) -> &InputState {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut InputState {
+        &mut self.input
+    }
+
+    /// Clicks the green flag, which stops everything and resets the timer before starting
+    /// the green flag scripts, like in Scratch.
+    pub fn click_green_flag(&mut self) -> Vec<Id<Task>> {
+        self.record_input(HostInput::GreenFlag);
+        self.stop_all();
+        self.reset_timer();
+        self.edge_values.clear();
+        self.edge_task_ids.clear();
+        self.dispatch(Trigger::OnStart)
+    }
+
+    /// Clicks the stop sign.
+    pub fn click_stop(&mut self) {
+        self.record_input(HostInput::Stop);
+        self.stop_all();
+    }
+
+    /// Presses a key, starting the "when key pressed" scripts for it and for any key.
+    /// Pressing a key that's already held down starts them again, like a repeating key.
+    /// Keys are named like in Scratch's key menus, such as `a`, `space` or `left arrow`.
+    pub fn press_key(&mut self, key: &str) -> Vec<Id<Task>> {
+        self.record_input(HostInput::KeyDown { key: key.into() });
+        self.input.press_key(key);
+
+        let key = scratch_key(&Value::from(key));
+        if key.is_empty() {
+            return Vec::new();
+        }
+
+        let mut started = self.dispatch(Trigger::key_pressed(&key));
+        started.extend(self.dispatch(Trigger::key_pressed("any")));
+        started
+    }
+
+    pub fn release_key(&mut self, key: &str) {
+        self.record_input(HostInput::KeyUp { key: key.into() });
+        self.input.release_key(key);
+    }
+
+    /// Moves the mouse pointer, keeping it on the stage and rounding its position to
+    /// whole pixels like Scratch does.
+    pub fn move_mouse(&mut self, x: f64, y: f64) {
+        self.record_input(HostInput::MouseMove { x, y });
+        self.place_mouse(x, y);
+    }
+
+    /// Moves the mouse pointer and presses the button, clicking the target under it.
+    pub fn mouse_down_at(&mut self, x: f64, y: f64) -> Vec<Id<Task>> {
+        self.record_input(HostInput::MouseDown { x, y });
+        self.place_mouse(x, y);
+        self.input.set_mouse_down(true);
+
+        let (x, y) = self.input.mouse_position();
+        self.click(self.target_at(x, y))
+    }
+
+    pub fn mouse_up(&mut self) {
+        self.record_input(HostInput::MouseUp);
+        self.input.set_mouse_down(false);
+    }
+
+    fn place_mouse(&mut self, x: f64, y: f64) {
+        let x = x.clamp(-STAGE_WIDTH / 2.0, STAGE_WIDTH / 2.0).round();
+        let y = y.clamp(-STAGE_HEIGHT / 2.0, STAGE_HEIGHT / 2.0).round();
+        self.input.set_mouse_position(x, y);
+    }
+
+    /// Clicks a sprite, clone or the stage, starting its "when this sprite clicked" or
+    /// "when stage clicked" scripts.
+    pub fn click_target(&mut self, target_id: usize) -> Vec<Id<Task>> {
+        self.record_input(HostInput::Click { target: target_id });
+        self.click(target_id)
+    }
+
+    fn click(&mut self, target_id: usize) -> Vec<Id<Task>> {
+        if self.targets[target_id].is_stage() {
+            self.dispatch_to(Trigger::StageClicked, target_id)
+        } else {
+            self.dispatch_to(Trigger::SpriteClicked, target_id)
+        }
+    }
+
+    /// The frontmost visible sprite whose bounds contain a point, or the stage if there
+    /// isn't one.
+    pub fn target_at(&self, x: f64, y: f64) -> usize {
+        let hit = self.layers.iter().rev().copied().find(|&id| {
+            let target = &self.targets[id];
+            target.sprite().is_some_and(|sprite| {
+                let bounds = sprite.bounds(target.costume());
+                sprite.visible
+                    && (bounds.left..=bounds.right).contains(&x)
+                    && (bounds.bottom..=bounds.top).contains(&y)
+            })
+        });
+
+        hit.unwrap_or_else(|| self.stage_id())
+    }
+
+    /// Starts recording the inputs given to the program, seeding its random numbers so that
+    /// the recording can be replayed with [`replay::replay`]. This should be done after the
+    /// program is configured and before it runs any frames. Inputs given through
+    /// [`Program::input_mut`] aren't recorded.
+    pub fn start_recording(&mut self, seed: u64) {
+        self.seed_rng(seed);
+        self.recording = Some(Recording::start(self, seed));
+    }
+
+    /// Finishes the recording, saving the final values of the program's variables in it.
+    /// Returns `None` if no recording was started.
+    pub fn finish_recording(&mut self) -> Option<Recording> {
+        let mut recording = self.recording.take()?;
+        recording.frame_count = self.frame_count;
+        recording.final_variables = replay::final_variables(self);
+        Some(recording)
+    }
+
+    /// Adds an input to the recording. Inputs given while a frame is running are seen by
+    /// that frame, and ones given between frames are seen by the next one.
+    fn record_input(&mut self, input: HostInput) {
+        let frame = if self.current_task.is_some() {
+            self.frame_count
+        } else {
+            self.frame_count + 1
+        };
+
+        if let Some(recording) = &mut self.recording {
+            recording.inputs.push(RecordedInput { frame, input });
+        }
+    }
+
+    /// The name reported by the username block, which is empty by default like it is for
+    /// signed out users.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn set_username(&mut self, username: impl Into<Arc<str>>) {
+        self.username = username.into();
+    }
+
+    /// Sets where answers to "ask and wait" blocks come from. Without a source, every
+    /// question is answered with empty text.
+    pub fn set_answer_source(&mut self, source: impl AnswerSource + 'static) {
+        self.answer_source = Some(Box::new(source));
+    }
+
+    /// The answer to the last question that was answered.
+    pub fn answer(&self) -> &Arc<str> {
+        &self.answer
+    }
+
+    /// Asks a question on behalf of a task, or checks on a question it already asked.
+    /// Returns whether the question has been answered, in which case the answer is
+    /// available from [`Program::answer`]. Questions are shown one at a time, and a
+    /// sprite that was visible when it asked says its question while it's being shown.
+    pub fn ask(&mut self, task: Id<Task>, target_id: usize, text: Arc<str>) -> bool {
+        // Questions from tasks that were stopped would never be answered
+        while let Some(question) = self.questions.front()
+            && !self.is_running(question.task)
+        {
+            self.finish_question();
+        }
+
+        let Some(position) = self.questions.iter().position(|q| q.task == task) else {
+            let shows_bubble = self.targets[target_id]
+                .sprite()
+                .is_some_and(|sprite| sprite.visible);
+            self.questions.push_back(Question {
+                task,
+                target_id,
+                text,
+                shows_bubble,
+            });
+            if self.questions.len() == 1 {
+                self.show_question();
+            }
+            return false;
+        };
+
+        if position > 0 {
+            return false;
+        }
+
+        let answer = match &mut self.answer_source {
+            Some(source) => source.answer(&text),
+            None => Some("".into()),
+        };
+        let Some(answer) = answer else {
+            return false;
+        };
+
+        self.record_input(HostInput::Answer {
+            text: answer.clone(),
+        });
+        self.answer = answer;
+        self.finish_question();
+        true
+    }
+
+    /// Shows the first question in the queue.
+    fn show_question(&mut self) {
+        let Some(question) = self.questions.front().cloned() else {
+            return;
+        };
+
+        if question.shows_bubble {
+            let bubble = Bubble {
+                kind: BubbleKind::Say,
+                text: question.text.clone(),
+                owner: question.task,
+                expires_at: None,
+            };
+            self.set_bubble(question.target_id, Some(bubble));
+        }
+
+        self.notify(|observer| observer.question_asked(question.target_id, &question.text));
+    }
+
+    /// Removes the first question in the queue and shows the next one.
+    fn finish_question(&mut self) {
+        let Some(question) = self.questions.pop_front() else {
+            return;
+        };
+
+        if question.shows_bubble {
+            self.set_bubble(question.target_id, None);
+        }
+
+        self.show_question();
+    }
+
+    /// Looks up a variable by name and reads its value. The stage's variables are the
+    /// global ones, and a sprite's are the ones local to it.
+    pub fn variable_by_name(&self, target_id: usize, name: &str) -> Option<Value> {
+        let vars = if self.targets[target_id].is_stage() {
+            &self.global_vars
+        } else {
+            &self.targets[target_id].vars
+        };
+
+        vars.iter()
+            .find(|var| &*var.name == name)
+            .map(|var| var.value.borrow().clone())
     }
 
     pub fn dbg_string(&self, value: &Value) -> Arc<str> {
@@ -182,52 +917,421 @@ impl Program {
     }
 
     pub fn with_var(&mut self, target_id: usize, id: Id<VarState>, cb: impl FnOnce(&mut Value)) {
+        let idx = id.get();
+
+        // Global variables belong to the stage
+        let (owner_id, var) = if let Some(idx) = idx.checked_sub(self.global_vars.len()) {
+            (target_id, &self.targets[target_id].vars[idx])
+        } else {
+            (self.stage_id(), &self.global_vars[idx])
+        };
+
+        cb(&mut var.as_ref().borrow_mut());
+
+        for observer in &mut self.observers {
+            observer.variable_changed(owner_id, var);
+        }
+    }
+
+    pub fn with_list<T>(
+        &mut self,
+        target_id: usize,
+        id: Id<ListState>,
+        cb: impl FnOnce(&mut Vec<Value>, &mut Rng) -> T,
+    ) -> T {
         let target = &mut self.targets[target_id];
         let idx = id.get();
 
-        if let Some(idx) = idx.checked_sub(self.global_vars.len()) {
-            cb(&mut *target.vars[idx].as_ref().borrow_mut());
+        let list = if let Some(idx) = idx.checked_sub(self.global_lists.len()) {
+            &target.lists[idx]
+        } else {
+            &self.global_lists[idx]
+        };
+
+        cb(&mut list.as_ref().borrow_mut(), &mut self.rng)
+    }
+
+    /// Like [`Program::with_list`], but notifies observers that the list changed.
+    pub fn modify_list<T>(
+        &mut self,
+        target_id: usize,
+        id: Id<ListState>,
+        cb: impl FnOnce(&mut Vec<Value>, &mut Rng) -> T,
+    ) -> T {
+        let result = self.with_list(target_id, id, cb);
+        let idx = id.get();
+
+        let (owner_id, list) = if let Some(idx) = idx.checked_sub(self.global_lists.len()) {
+            (target_id, &self.targets[target_id].lists[idx])
         } else {
-            cb(&mut *self.global_vars[idx].as_ref().borrow_mut());
+            (self.stage_id(), &self.global_lists[idx])
+        };
+
+        for observer in &mut self.observers {
+            observer.list_changed(owner_id, list);
+        }
+
+        result
+    }
+
+    pub fn list_contents(&self, target_id: usize, id: Id<ListState>) -> Arc<str> {
+        let target = &self.targets[target_id];
+        let idx = id.get();
+
+        if let Some(idx) = idx.checked_sub(self.global_lists.len()) {
+            target.lists[idx].contents()
+        } else {
+            self.global_lists[idx].contents()
+        }
+    }
+
+    pub fn warp_time(&self) -> Duration {
+        self.warp_time
+    }
+
+    pub fn set_warp_time(&mut self, warp_time: Duration) {
+        self.warp_time = warp_time;
+    }
+
+    pub fn targets(&self) -> &[TargetScope] {
+        &self.targets
+    }
+
+    pub fn target(&self, target_id: usize) -> &TargetScope {
+        &self.targets[target_id]
+    }
+
+    /// Gets a target to modify. Observers are told that the target changed once the
+    /// current block finishes running.
+    pub fn target_mut(&mut self, target_id: usize) -> &mut TargetScope {
+        if !self.changed_targets.contains(&target_id) {
+            self.changed_targets.push(target_id);
+        }
+        &mut self.targets[target_id]
+    }
+
+    /// Notifies observers about the targets that blocks have modified.
+    fn flush_target_changes(&mut self) {
+        for target_id in mem::take(&mut self.changed_targets) {
+            let target = &self.targets[target_id];
+            for observer in &mut self.observers {
+                observer.target_changed(target_id, target);
+            }
+        }
+    }
+
+    /// Finds a sprite or the stage by name. Clones share their sprite's name, so they're
+    /// never found.
+    pub fn find_target(&self, name: &str) -> Option<usize> {
+        self.targets
+            .iter()
+            .position(|target| target.name() == name && !target.is_clone())
+    }
+
+    /// Whether a target exists. Only deleted clones don't.
+    pub fn is_alive(&self, target_id: usize) -> bool {
+        target_id < self.targets.len() && !self.free_slots.contains(&target_id)
+    }
+
+    /// The id of the sprite a target was cloned from, or the target's own id if it isn't
+    /// a clone.
+    pub fn original_id(&self, target_id: usize) -> usize {
+        self.targets[target_id].clone_of.unwrap_or(target_id)
+    }
+
+    /// A sprite followed by each of its clones that still exist.
+    fn instances_of(&self, original_id: usize) -> Vec<usize> {
+        let clones = self.clones().filter(|&id| self.targets[id].clone_of == Some(original_id));
+        std::iter::once(original_id).chain(clones).collect()
+    }
+
+    /// The ids of every clone that still exists.
+    pub fn clones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.targets.len())
+            .filter(|&id| self.targets[id].is_clone() && self.is_alive(id))
+    }
+
+    pub fn clone_limit(&self) -> usize {
+        self.clone_limit
+    }
+
+    /// Sets how many clones can exist at once. Clones over the limit aren't deleted, but
+    /// no more can be created until enough of them are.
+    pub fn set_clone_limit(&mut self, limit: usize) {
+        self.clone_limit = limit;
+    }
+
+    /// Creates a clone of a sprite or of another clone, which gets its own copy of the
+    /// parent's variables, lists and sprite state, and is drawn just behind it. The
+    /// clone's "when I start as a clone" scripts are started, and its id is returned.
+    ///
+    /// Returns `None` if the parent is the stage, or if the clone limit has been reached.
+    pub fn create_clone(&mut self, parent_id: usize) -> Option<usize> {
+        let parent = &self.targets[parent_id];
+        if parent.is_stage() || self.clones().count() >= self.clone_limit {
+            return None;
         }
+
+        let clone = parent.clone_from_parent(self.original_id(parent_id));
+        let target_id = match self.free_slots.pop() {
+            Some(slot) => {
+                self.targets[slot] = clone;
+                slot
+            }
+            None => {
+                self.targets.push(clone);
+                self.targets.len() - 1
+            }
+        };
+
+        let layer = self.layer_of(parent_id);
+        self.layers.insert(layer, target_id);
+
+        self.notify(|observer| observer.clone_created(target_id, parent_id));
+        self.dispatch_to(Trigger::CloneStarted, target_id);
+        Some(target_id)
+    }
+
+    /// Deletes a clone, stopping its tasks. Returns whether the target was a clone that
+    /// still existed.
+    pub fn delete_clone(&mut self, target_id: usize) -> bool {
+        if !self.is_alive(target_id) || !self.targets[target_id].is_clone() {
+            return false;
+        }
+
+        let ids = self
+            .tasks()
+            .filter(|task| task.target_id == target_id)
+            .map(|task| task.id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.kill_task(id);
+        }
+
+        if self.current_task.is_some_and(|current| current.target_id == target_id) {
+            self.stop_current = true;
+        }
+
+        self.set_bubble(target_id, None);
+        self.edge_values.retain(|&(_, id), _| id != target_id);
+        self.edge_task_ids.retain(|&(_, id), _| id != target_id);
+        self.layers.retain(|&id| id != target_id);
+        self.changed_targets.retain(|&id| id != target_id);
+        self.free_slots.push(target_id);
+
+        self.notify(|observer| observer.clone_deleted(target_id));
+        true
+    }
+
+    pub fn stage_id(&self) -> usize {
+        self.targets
+            .iter()
+            .position(|target| target.is_stage())
+            .expect("program should have a stage")
+    }
+
+    pub fn stage(&self) -> &TargetScope {
+        &self.targets[self.stage_id()]
+    }
+
+    pub fn stage_mut(&mut self) -> &mut TargetScope {
+        let stage_id = self.stage_id();
+        self.target_mut(stage_id)
+    }
+
+    /// The ids of every target from the back layer to the front layer.
+    pub fn layers(&self) -> &[usize] {
+        &self.layers
+    }
+
+    /// Replaces the layer order, which must contain each target exactly once with the
+    /// stage at the back.
+    pub fn set_layers(&mut self, layers: Vec<usize>) {
+        assert_eq!(layers.len(), self.targets.len(), "every target must have a layer");
+        assert!(
+            layers.first().is_none_or(|&id| self.targets[id].is_stage()),
+            "stage must be the back layer"
+        );
+        self.layers = layers;
+    }
+
+    /// The layer a target is drawn on, where 0 is the back layer.
+    pub fn layer_of(&self, target_id: usize) -> usize {
+        self.layers
+            .iter()
+            .position(|&id| id == target_id)
+            .expect("every target should have a layer")
+    }
+
+    /// Moves a sprite to the given layer, keeping it in front of the stage. Does nothing
+    /// for the stage.
+    pub fn set_layer(&mut self, target_id: usize, layer: usize) {
+        if self.targets[target_id].is_stage() {
+            return;
+        }
+
+        let current = self.layer_of(target_id);
+        self.layers.remove(current);
+
+        let layer = layer.clamp(1, self.layers.len());
+        self.layers.insert(layer, target_id);
+        self.target_mut(target_id);
+    }
+
+    pub fn move_to_front(&mut self, target_id: usize) {
+        self.set_layer(target_id, usize::MAX);
+    }
+
+    pub fn move_to_back(&mut self, target_id: usize) {
+        self.set_layer(target_id, 1);
+    }
+
+    /// Moves a sprite forward by a number of layers, or backward if it's negative.
+    pub fn move_layers(&mut self, target_id: usize, delta: isize) {
+        let layer = self.layer_of(target_id).saturating_add_signed(delta);
+        self.set_layer(target_id, layer);
+    }
+
+    pub fn rng_mut(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Task {
+    id: Id<Task>,
+    /// The script the task was started with.
+    root: Rc<ProcedureValue>,
+    /// The target the task runs on, which is a clone if the task belongs to one.
+    target_id: usize,
     procedure: Rc<ProcedureValue>,
     location: usize,
+    /// The location of the instruction that's running, or that ran last.
+    pc: usize,
     scopes: Vec<Box<[Value]>>,
     stack: Vec<Value>,
     complete: bool,
-    wake_time: Instant,
+    wake_time: Duration,
+    /// The number of scopes that were active when this task entered warp mode. Yields
+    /// are ignored until the warp procedure that owns that scope returns.
+    warp_depth: Option<usize>,
+    /// When this task started running in warp mode during the current frame.
+    warp_started: Option<Duration>,
+    /// How many yields this task has skipped since `warp_started`.
+    warp_yields: u32,
+    /// The tasks started by the last broadcast this task sent, which broadcast-and-wait
+    /// waits on.
+    started_tasks: Vec<Id<Task>>,
+    /// Whether the task is checking an edge-activated hat and hasn't got past it yet.
+    checking_edge: bool,
 }
 
 impl Task {
-    pub fn new(procedure: Rc<ProcedureValue>) -> Self {
+    pub fn new(id: Id<Task>, procedure: Rc<ProcedureValue>, target_id: usize) -> Self {
         assert_eq!(procedure.param_count, 0);
         let scope = vec![Value::default(); procedure.locals.len()];
+        let warp_depth = procedure.warp.then_some(1);
 
         Self {
+            id,
+            root: procedure.clone(),
+            target_id,
             procedure,
             location: 0,
+            pc: 0,
             scopes: vec![scope.into_boxed_slice()],
             stack: Vec::with_capacity(10),
             complete: false,
-            wake_time: Instant::now(),
+            wake_time: Duration::ZERO,
+            warp_depth,
+            warp_started: None,
+            warp_yields: 0,
+            started_tasks: Vec::new(),
+            checking_edge: false,
         }
     }
 
+    /// Starts the task's script over from the top, discarding its state.
+    fn restart(&mut self) {
+        let scope = vec![Value::default(); self.root.locals.len()];
+
+        self.procedure = self.root.clone();
+        self.location = 0;
+        self.pc = 0;
+        self.scopes = vec![scope.into_boxed_slice()];
+        self.stack.clear();
+        self.complete = false;
+        self.warp_depth = self.root.warp.then_some(1);
+        self.warp_started = None;
+        self.warp_yields = 0;
+        self.started_tasks.clear();
+    }
+
+    /// The tasks started by the last broadcast this task sent.
+    pub fn started_tasks(&self) -> &[Id<Task>] {
+        &self.started_tasks
+    }
+
+    /// Sends a broadcast on behalf of this task, remembering which tasks it started.
+    fn broadcast(&mut self, program: &mut Program, event: Id<EventValue>) {
+        if program.trace_level >= TraceLevel::Broadcasts {
+            let name = program.events[event.get()].name().to_owned();
+            let trace_event = TraceEvent::Broadcast { event, name: &name };
+            program.trace(self.id, &self.procedure, self.pc, trace_event);
+        }
+
+        self.started_tasks = program.broadcast(event);
+    }
+
+    pub fn id(&self) -> Id<Task> {
+        self.id
+    }
+
+    /// The target the task runs on, which is a clone if the task belongs to one.
+    pub fn target_id(&self) -> usize {
+        self.target_id
+    }
+
+    /// The script the task was started with.
+    pub fn script(&self) -> &ProcedureValue {
+        &self.root
+    }
+
+    /// The block that the running instruction was compiled from, if it came from one.
+    pub fn current_block(&self) -> Option<&BlockSource> {
+        self.procedure.source_map().block_at(self.pc)
+    }
+
+    pub fn is_warping(&self) -> bool {
+        self.warp_depth.is_some()
+    }
+
+    /// Whether a yield should actually pause this task. Tasks in warp mode ignore yields
+    /// unless they've been running for longer than the program's warp time.
+    fn should_yield(&mut self, program: &Program) -> bool {
+        if !self.is_warping() {
+            return true;
+        }
+
+        let warp_started = *self.warp_started.get_or_insert_with(|| program.clock.now());
+        self.warp_yields += 1;
+        program.clock.warp_elapsed(warp_started, self.warp_yields) > program.warp_time
+    }
+
     pub fn is_complete(&self) -> bool {
         self.complete
     }
 
-    pub fn sleep_until(&mut self, wake_time: Instant) {
+    pub fn sleep_until(&mut self, wake_time: Duration) {
         self.wake_time = wake_time;
     }
 
-    pub fn is_done_sleeping(&self) -> bool {
-        Instant::now() >= self.wake_time
+    pub fn is_done_sleeping(&self, now: Duration) -> bool {
+        now >= self.wake_time
     }
 
     pub fn stack(&self) -> &Vec<Value> {
@@ -300,9 +1404,9 @@ impl Task {
     }
 
     fn run_until_yield(&mut self, program: &mut Program) {
-        // Wake time is used as priority, so reset this task's priority to
-        // send it to the back of the queue because we are running it.
-        self.wake_time = Instant::now();
+        // The warp timer only measures how long the task has been running this frame.
+        self.warp_started = self.is_warping().then(|| program.clock.now());
+        self.warp_yields = 0;
 
         loop {
             if self.location >= self.procedure.bytecode().len() {
@@ -317,14 +1421,17 @@ impl Task {
     }
 
     fn run_opcode(&mut self, program: &mut Program) -> bool {
+        let pc = self.location;
+        self.pc = pc;
         let opcode = self.read_opcode();
 
-        let debug_message = format!(
-            "$ {opcode:?} proc={:?} stack={:?}",
-            self.procedure.name(),
-            self.stack,
-        );
-        println!("{}", debug_message.bright_black());
+        if program.trace_level >= TraceLevel::Opcodes {
+            let event = TraceEvent::Opcode {
+                opcode,
+                stack: &self.stack,
+            };
+            program.trace(self.id, &self.procedure, pc, event);
+        }
 
         match opcode {
             Opcode::PushConstant => {
@@ -351,11 +1458,10 @@ impl Task {
 
             Opcode::DispatchEvent => {
                 let id = Id::<EventValue>::from(self.read_immediate() as usize);
-                let dbg_msg = format!("> {}", program.dbg_string(&id.into()));
-                println!("  {}", dbg_msg.bright_black());
-                program.dispatch(Trigger::Event(id));
+                self.broadcast(program, id);
 
-                return true;
+                // Broadcasting doesn't yield, unless this task's own script was restarted
+                return program.restart_current || program.stop_current;
             }
             Opcode::CallBuiltin => {
                 let imm = self.read_immediate();
@@ -375,18 +1481,21 @@ impl Task {
                 }
 
                 program.builtins = Some(library);
+                program.flush_target_changes();
 
-                return true;
+                // Builtins can stop or restart the task that called them
+                if program.restart_current || program.stop_current {
+                    return true;
+                }
             }
             Opcode::CallProcedure => {
                 let proc_id = self.read_immediate() as usize;
                 let procedure = program.procedures[proc_id].clone();
 
                 let mut scope = Vec::with_capacity(procedure.locals.len());
-                // Add locals initialized from parameters in the stack
-                for _ in 0..procedure.param_count {
-                    scope.push(self.stack.pop().unwrap());
-                }
+                // Add locals initialized from parameters in the stack, which are pushed in order
+                let args_start = self.stack.len() - procedure.param_count;
+                scope.extend(self.stack.drain(args_start..));
                 // Add uninitialized locals
                 while scope.len() < procedure.locals.len() {
                     scope.push(Value::default());
@@ -402,6 +1511,12 @@ impl Task {
                 self.location = 0;
                 self.procedure = procedure;
                 self.enter_scope(scope.into_boxed_slice());
+
+                if self.procedure.warp && !self.is_warping() {
+                    self.warp_depth = Some(self.scopes.len());
+                    self.warp_started = Some(program.clock.now());
+                    self.warp_yields = 0;
+                }
             }
 
             Opcode::Jump => {
@@ -432,22 +1547,31 @@ impl Task {
                 let procedure_id = procedure_id.unwrap_procedure();
 
                 self.leave_scope();
+                if self.warp_depth.is_some_and(|depth| self.scopes.len() < depth) {
+                    self.warp_depth = None;
+                    self.warp_started = None;
+                    self.warp_yields = 0;
+                }
+
                 self.procedure = program.procedures[procedure_id.get()].clone();
                 self.location = self.stack.pop().unwrap().unwrap_return_location();
             }
             Opcode::Yield => {
-                return true;
+                if self.should_yield(program) {
+                    return true;
+                }
             }
             Opcode::Sleep => {
                 let [duration_secs] = self.pop_numbers();
-                self.wake_time = Instant::now() + Duration::from_secs_f64(duration_secs);
+                let duration = Duration::try_from_secs_f64(duration_secs).unwrap_or_default();
+                self.wake_time = program.now() + duration;
                 return true;
             }
 
             Opcode::SetVar => {
                 let new_value = self.stack.pop().unwrap();
                 program.set_var(
-                    self.procedure.target_id,
+                    self.target_id,
                     self.read_id::<VarState>(),
                     new_value,
                 );
@@ -455,7 +1579,7 @@ impl Task {
             Opcode::ChangeVar => {
                 let offset = self.stack.pop().unwrap();
                 program.with_var(
-                    self.procedure.target_id,
+                    self.target_id,
                     self.read_id::<VarState>(),
                     |var| {
                         *var = Value::Number(var.cast_number() + offset.cast_number());
@@ -464,14 +1588,14 @@ impl Task {
             }
             Opcode::ClearVar => {
                 program.set_var(
-                    self.procedure.target_id,
+                    self.target_id,
                     self.read_id::<VarState>(),
                     Value::default(),
                 );
             }
             Opcode::ZeroVar => {
                 program.set_var(
-                    self.procedure.target_id,
+                    self.target_id,
                     self.read_id::<VarState>(),
                     Value::Number(0.0),
                 );
@@ -479,7 +1603,7 @@ impl Task {
             Opcode::PushVar => {
                 let id = self.read_id::<VarState>();
                 self.stack
-                    .push(program.read_var(self.procedure.target_id, id));
+                    .push(program.read_var(self.target_id, id));
             }
 
             Opcode::SetLocal => {
@@ -497,15 +1621,159 @@ impl Task {
                 self.set_local(idx, Value::Number(old - 1.0));
             }
 
+            Opcode::PushList => {
+                let id = self.read_id::<ListState>();
+                let contents = program.list_contents(self.target_id, id);
+                self.stack.push(Value::String(contents));
+            }
+            Opcode::ListAdd => {
+                let id = self.read_id::<ListState>();
+                let item = self.pop();
+                program.modify_list(self.target_id, id, |list, _| {
+                    if list.len() < ListState::MAX_ITEMS {
+                        list.push(item);
+                    }
+                });
+            }
+            Opcode::ListDelete => {
+                let id = self.read_id::<ListState>();
+                let index = self.pop();
+                program.modify_list(self.target_id, id, |list, rng| {
+                    match index.cast_list_index(list.len(), true, rng) {
+                        ListIndex::Item(idx) => {
+                            list.remove(idx - 1);
+                        }
+                        ListIndex::All => list.clear(),
+                        ListIndex::Invalid => {}
+                    }
+                });
+            }
+            Opcode::ListDeleteAll => {
+                let id = self.read_id::<ListState>();
+                program.modify_list(self.target_id, id, |list, _| list.clear());
+            }
+            Opcode::ListInsert => {
+                let id = self.read_id::<ListState>();
+                let [item, index] = self.pop_values();
+                program.modify_list(self.target_id, id, |list, rng| {
+                    if list.len() >= ListState::MAX_ITEMS {
+                        return;
+                    }
+                    // Inserting is allowed one past the end of the list
+                    if let ListIndex::Item(idx) = index.cast_list_index(list.len() + 1, false, rng) {
+                        list.insert(idx - 1, item);
+                    }
+                });
+            }
+            Opcode::ListReplace => {
+                let id = self.read_id::<ListState>();
+                let [index, item] = self.pop_values();
+                program.modify_list(self.target_id, id, |list, rng| {
+                    if let ListIndex::Item(idx) = index.cast_list_index(list.len(), false, rng) {
+                        list[idx - 1] = item;
+                    }
+                });
+            }
+            Opcode::ListItem => {
+                let id = self.read_id::<ListState>();
+                let index = self.pop();
+                let item = program.with_list(self.target_id, id, |list, rng| {
+                    match index.cast_list_index(list.len(), false, rng) {
+                        ListIndex::Item(idx) => list[idx - 1].clone(),
+                        _ => Value::default(),
+                    }
+                });
+                self.stack.push(item);
+            }
+            Opcode::ListLength => {
+                let id = self.read_id::<ListState>();
+                let length = program.with_list(self.target_id, id, |list, _| list.len());
+                self.stack.push(Value::Number(length as f64));
+            }
+            Opcode::ListContains => {
+                let id = self.read_id::<ListState>();
+                let item = self.pop();
+                let contains = program.with_list(self.target_id, id, |list, _| {
+                    list.iter().any(|i| i.compare(&item).is_eq())
+                });
+                self.stack.push(Value::Boolean(contains));
+            }
+            Opcode::ListIndexOf => {
+                let id = self.read_id::<ListState>();
+                let item = self.pop();
+                let position = program.with_list(self.target_id, id, |list, _| {
+                    list.iter()
+                        .position(|i| i.compare(&item).is_eq())
+                        .map_or(0, |idx| idx + 1)
+                });
+                self.stack.push(Value::Number(position as f64));
+            }
+
             Opcode::Add => {
                 let [left, right] = self.pop_numbers::<2>();
                 let result = left + right;
                 self.stack.push(result.into());
             }
 
-            Opcode::GreaterThan => {
+            Opcode::Subtract => {
+                let [left, right] = self.pop_numbers::<2>();
+                self.stack.push((left - right).into());
+            }
+            Opcode::Multiply => {
+                let [left, right] = self.pop_numbers::<2>();
+                self.stack.push((left * right).into());
+            }
+            Opcode::Divide => {
+                let [left, right] = self.pop_numbers::<2>();
+                self.stack.push((left / right).into());
+            }
+            Opcode::Modulo => {
                 let [left, right] = self.pop_numbers::<2>();
-                self.stack.push(Value::Boolean(left > right));
+                // Scratch's modulo takes the sign of the divisor, unlike Rust's remainder.
+                let mut result = left % right;
+                if result / right < 0.0 {
+                    result += right;
+                }
+                self.stack.push(result.into());
+            }
+            Opcode::Round => {
+                let [num] = self.pop_numbers();
+                // Round half towards positive infinity, like JavaScript's Math.round.
+                let mut result = num.round();
+                if result - num == -0.5 {
+                    result += 1.0;
+                }
+                self.stack.push(result.into());
+            }
+            Opcode::MathOp => {
+                let op = MathOp::try_from_primitive(self.read_immediate()).unwrap();
+                let [num] = self.pop_numbers();
+                self.stack.push(op.apply(num).into());
+            }
+
+            Opcode::GreaterThan => {
+                let [left, right] = self.pop_values();
+                self.stack.push(Value::Boolean(left.compare(&right).is_gt()));
+            }
+            Opcode::LessThan => {
+                let [left, right] = self.pop_values();
+                self.stack.push(Value::Boolean(left.compare(&right).is_lt()));
+            }
+            Opcode::Equals => {
+                let [left, right] = self.pop_values();
+                self.stack.push(Value::Boolean(left.compare(&right).is_eq()));
+            }
+            Opcode::And => {
+                let [left, right] = self.pop_values();
+                self.stack.push(Value::Boolean(left.cast_boolean() && right.cast_boolean()));
+            }
+            Opcode::Or => {
+                let [left, right] = self.pop_values();
+                self.stack.push(Value::Boolean(left.cast_boolean() || right.cast_boolean()));
+            }
+            Opcode::Not => {
+                let value = self.pop();
+                self.stack.push(Value::Boolean(!value.cast_boolean()));
             }
 
             other => {
@@ -542,20 +1810,29 @@ impl Task {
     }
 }
 
+/// What the program knows about the task that's running, which is taken out of the
+/// queue while it runs.
+#[derive(Debug, Clone, Copy)]
+struct RunningTask {
+    id: Id<Task>,
+    script: Id<ProcedureValue>,
+    target_id: usize,
+}
+
 #[derive(Debug)]
-struct Sleeper(Task);
+struct Sleeper(Task, u64);
 
 impl Eq for Sleeper {}
 
 impl PartialEq for Sleeper {
     fn eq(&self, other: &Self) -> bool {
-        self.0.wake_time == other.0.wake_time
+        self.0.wake_time == other.0.wake_time && self.1 == other.1
     }
 }
 
 impl Ord for Sleeper {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.wake_time.cmp(&other.0.wake_time)
+        (self.0.wake_time, self.1).cmp(&(other.0.wake_time, other.1))
     }
 }
 
@@ -567,18 +1844,164 @@ impl PartialOrd for Sleeper {
 
 #[derive(Debug)]
 pub struct TargetScope {
+    name: Arc<str>,
     vars: Vec<VarState>,
+    lists: Vec<ListState>,
+    costumes: Arc<[Costume]>,
+    sounds: Arc<[Sound]>,
+    current_costume: usize,
+    effects: GraphicEffects,
+    sprite: Option<SpriteState>,
+    /// The sprite this target is a clone of, whose scripts it runs.
+    clone_of: Option<usize>,
 }
 
 impl TargetScope {
-    pub const fn new(vars: Vec<VarState>) -> Self {
-        Self { vars }
+    pub fn new(name: impl Into<Arc<str>>, vars: Vec<VarState>, lists: Vec<ListState>) -> Self {
+        Self {
+            name: name.into(),
+            vars,
+            lists,
+            costumes: Arc::new([]),
+            sounds: Arc::new([]),
+            current_costume: 0,
+            effects: GraphicEffects::default(),
+            sprite: None,
+            clone_of: None,
+        }
+    }
+
+    /// Copies the target's state for a new clone of the given sprite. Speech bubbles
+    /// aren't copied.
+    fn clone_from_parent(&self, original_id: usize) -> Self {
+        Self {
+            name: self.name.clone(),
+            vars: self.vars.clone(),
+            lists: self.lists.clone(),
+            costumes: self.costumes.clone(),
+            sounds: self.sounds.clone(),
+            current_costume: self.current_costume,
+            effects: self.effects.clone(),
+            sprite: self.sprite.clone().map(|sprite| SpriteState {
+                bubble: None,
+                ..sprite
+            }),
+            clone_of: Some(original_id),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_stage(&self) -> bool {
+        self.sprite.is_none()
+    }
+
+    pub fn is_clone(&self) -> bool {
+        self.clone_of.is_some()
+    }
+
+    /// The sprite this target is a clone of, if it's a clone.
+    pub fn clone_of(&self) -> Option<usize> {
+        self.clone_of
+    }
+
+    /// The state of this target if it's a sprite, or `None` for the stage.
+    pub fn sprite(&self) -> Option<&SpriteState> {
+        self.sprite.as_ref()
+    }
+
+    pub fn sprite_mut(&mut self) -> Option<&mut SpriteState> {
+        self.sprite.as_mut()
+    }
+
+    pub fn costumes(&self) -> &[Costume] {
+        &self.costumes
+    }
+
+    pub fn sounds(&self) -> &[Sound] {
+        &self.sounds
+    }
+
+    pub fn current_costume(&self) -> usize {
+        self.current_costume
+    }
+
+    pub fn costume(&self) -> Option<&Costume> {
+        self.costumes.get(self.current_costume)
+    }
+
+    /// Moves the sprite to a position, keeping it partially on the stage. Does nothing for
+    /// the stage.
+    pub fn set_xy(&mut self, x: f64, y: f64) {
+        let costume = self.costumes.get(self.current_costume);
+        if let Some(sprite) = &mut self.sprite {
+            (sprite.x, sprite.y) = sprite.fenced_position(costume, x, y);
+        }
+    }
+
+    /// Switches to the costume at the given index, ignoring indices that are out of range.
+    pub fn set_costume(&mut self, idx: usize) {
+        if idx < self.costumes.len() {
+            self.current_costume = idx;
+        }
+    }
+
+    /// Switches to a costume by its zero-based index, rounding it and wrapping it around
+    /// the list of costumes like Scratch does.
+    pub fn set_costume_wrapped(&mut self, idx: f64) {
+        let Some(max) = self.costumes.len().checked_sub(1) else {
+            return;
+        };
+
+        let idx = idx.round();
+        let idx = if idx.is_finite() { idx } else { 0.0 };
+        self.current_costume = wrap_clamp(idx, 0.0, max as f64) as usize;
+    }
+
+    pub fn costume_index(&self, name: &str) -> Option<usize> {
+        self.costumes.iter().position(|costume| &*costume.name == name)
+    }
+
+    pub fn next_costume(&mut self) {
+        self.set_costume_wrapped(self.current_costume as f64 + 1.0);
+    }
+
+    pub fn previous_costume(&mut self) {
+        self.set_costume_wrapped(self.current_costume as f64 - 1.0);
+    }
+
+    /// Sets the sprite's size as a percentage, limited by the size of its costume.
+    pub fn set_size(&mut self, size: f64) {
+        let costume = self.costumes.get(self.current_costume);
+        if let Some(sprite) = &mut self.sprite {
+            sprite.set_size(costume, size);
+        }
+    }
+
+    pub fn effects(&self) -> &GraphicEffects {
+        &self.effects
+    }
+
+    pub fn effects_mut(&mut self) -> &mut GraphicEffects {
+        &mut self.effects
     }
 }
 
 impl From<&Target> for TargetScope {
     fn from(value: &Target) -> Self {
-        Self::new(value.variables.values().map(|v| v.initialize()).collect())
+        Self {
+            name: value.name.clone(),
+            vars: value.variables.values().map(|v| v.initialize()).collect(),
+            lists: value.lists.values().map(|l| l.initialize()).collect(),
+            costumes: value.costumes.clone().into(),
+            sounds: value.sounds.clone().into(),
+            current_costume: value.current_costume,
+            effects: GraphicEffects::default(),
+            sprite: value.sprite.as_ref().map(SpriteState::from),
+            clone_of: None,
+        }
     }
 }
 
@@ -603,4 +2026,58 @@ impl RuntimeContext<'_> {
     pub const fn program_mut(&mut self) -> &mut Program {
         self.program
     }
+
+    /// The id of the target the running script belongs to.
+    pub fn target_id(&self) -> usize {
+        self.task.target_id
+    }
+
+    pub fn target(&self) -> &TargetScope {
+        &self.program.targets[self.target_id()]
+    }
+
+    pub fn target_mut(&mut self) -> &mut TargetScope {
+        let target_id = self.target_id();
+        self.program.target_mut(target_id)
+    }
+
+    /// Records the latest value of an edge-activated hat's condition, and returns whether
+    /// it has just become true. The task is reported to observers as started once it has.
+    pub fn update_edge(&mut self, value: bool) -> bool {
+        let key = (self.task.root.id(), self.task.target_id);
+        let previous = self.program.edge_values.insert(key, value);
+        let rising = value && previous != Some(true);
+
+        if rising && mem::take(&mut self.task.checking_edge) {
+            self.program.edge_task_ids.remove(&key);
+            let task = &*self.task;
+            self.program.notify(|observer| observer.task_started(task));
+        }
+
+        rising
+    }
+
+    /// The state of the sprite the running script belongs to, or `None` if the script
+    /// belongs to the stage.
+    pub fn sprite(&self) -> Option<&SpriteState> {
+        self.target().sprite()
+    }
+
+    pub fn sprite_mut(&mut self) -> Option<&mut SpriteState> {
+        self.target_mut().sprite_mut()
+    }
+
+    /// Sends a broadcast from the running task, which remembers the tasks it started.
+    pub fn broadcast(&mut self, event: Id<EventValue>) {
+        self.task.broadcast(self.program, event);
+    }
+
+    /// Sends a broadcast by name, ignoring case. Unknown names start nothing, so the task
+    /// forgets the tasks its last broadcast started rather than waiting on them again.
+    pub fn broadcast_named(&mut self, name: &str) {
+        match self.program.find_event(name) {
+            Some(event) => self.broadcast(event),
+            None => self.task.started_tasks.clear(),
+        }
+    }
 }
@@ -0,0 +1,192 @@
+use std::{
+    fmt::Debug,
+    thread::sleep,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The source of time for a [`Program`](super::Program). Times are measured from when
+/// the clock was created.
+pub trait Clock: Debug {
+    fn now(&self) -> Duration;
+
+    /// The date and time as a duration since the Unix epoch, which blocks that report the
+    /// current date use.
+    fn unix_time(&self) -> Duration;
+
+    /// Waits until [`Clock::now`] reaches the given time. Does nothing if that time has
+    /// already passed.
+    fn wait_until(&mut self, time: Duration);
+
+    /// How long a task has been running in warp mode, given the time it started at and
+    /// the number of yields it has skipped since.
+    fn warp_elapsed(&self, started: Duration, skipped_yields: u32) -> Duration;
+}
+
+/// A clock that follows wall-clock time and actually sleeps while waiting.
+#[derive(Debug, Clone)]
+pub struct RealClock {
+    start: Instant,
+    start_unix_time: Duration,
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_unix_time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn unix_time(&self) -> Duration {
+        self.start_unix_time + self.now()
+    }
+
+    fn wait_until(&mut self, time: Duration) {
+        if let Some(delay) = time.checked_sub(self.now()) {
+            sleep(delay);
+        }
+    }
+
+    fn warp_elapsed(&self, started: Duration, _skipped_yields: u32) -> Duration {
+        self.now().saturating_sub(started)
+    }
+}
+
+/// A clock that only moves forward when waited on, so that a program runs as fast as
+/// possible and behaves identically every time. Its date starts at the Unix epoch unless
+/// another start date is given.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Duration,
+    start_unix_time: Duration,
+}
+
+impl VirtualClock {
+    /// How long each yield skipped in warp mode counts for. Time doesn't pass while a task
+    /// runs, so warp loops are measured in iterations instead.
+    pub const WARP_YIELD_TIME: Duration = Duration::from_micros(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a clock whose date starts at the given duration since the Unix epoch.
+    pub fn starting_at(unix_time: Duration) -> Self {
+        Self {
+            now: Duration::ZERO,
+            start_unix_time: unix_time,
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn unix_time(&self) -> Duration {
+        self.start_unix_time + self.now
+    }
+
+    fn wait_until(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+
+    fn warp_elapsed(&self, _started: Duration, skipped_yields: u32) -> Duration {
+        Self::WARP_YIELD_TIME * skipped_yields
+    }
+}
+
+/// A date and time in UTC, broken down into the parts that Scratch reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CivilTime {
+    pub year: i64,
+    /// The month, from 1 for January.
+    pub month: u32,
+    /// The day of the month, from 1.
+    pub day: u32,
+    /// The day of the week, from 1 for Sunday.
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl CivilTime {
+    /// Breaks down a duration since the Unix epoch into a date and time in UTC.
+    pub fn from_unix_time(unix_time: Duration) -> Self {
+        let secs = unix_time.as_secs();
+        let days = (secs / 86_400) as i64;
+        let secs_of_day = (secs % 86_400) as u32;
+
+        // Converts days to a date in the proleptic Gregorian calendar, counting from
+        // March so that leap days fall at the end of the year
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = ((shifted_month + 2) % 12 + 1) as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            // The epoch was a Thursday
+            weekday: ((days + 4).rem_euclid(7) + 1) as u32,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::CivilTime;
+
+    #[test]
+    fn civil_time() {
+        // Unix time, then year, month, day, weekday, hour, minute and second
+        let cases = [
+            (0, (1970, 1, 1, 5, 0, 0, 0)),
+            (951_827_445, (2000, 2, 29, 3, 12, 30, 45)),
+            (951_868_800, (2000, 3, 1, 4, 0, 0, 0)),
+            (4_107_542_400, (2100, 3, 1, 2, 0, 0, 0)),
+            (946_684_799, (1999, 12, 31, 6, 23, 59, 59)),
+        ];
+
+        for (secs, (year, month, day, weekday, hour, minute, second)) in cases {
+            let expected = CivilTime {
+                year,
+                month,
+                day,
+                weekday,
+                hour,
+                minute,
+                second,
+            };
+            assert_eq!(CivilTime::from_unix_time(Duration::from_secs(secs)), expected, "{secs}");
+        }
+    }
+}
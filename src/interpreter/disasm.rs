@@ -0,0 +1,323 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use derive_more::{Display, Error};
+use num_enum::TryFromPrimitive;
+
+use crate::interpreter::{
+    Program,
+    opcode::{MathOp, Opcode, Trigger},
+    value::{ProcedureValue, Value},
+};
+
+/// A single decoded instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction<'a> {
+    /// The location of the opcode in the procedure's bytecode.
+    pub location: usize,
+    pub opcode: Opcode,
+    pub immediates: &'a [u32],
+}
+
+impl Instruction<'_> {
+    /// The location the instruction jumps to, if it's a jump.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.opcode {
+            Opcode::Jump | Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                Some(self.immediates[0] as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum DecodeError {
+    #[display("invalid opcode {opcode} at {location:06}")]
+    InvalidOpcode {
+        location: usize,
+        #[error(not(source))]
+        opcode: u32,
+    },
+    #[display("{opcode:?} at {location:06} is missing its immediates")]
+    Truncated {
+        location: usize,
+        #[error(not(source))]
+        opcode: Opcode,
+    },
+}
+
+/// Splits bytecode into instructions.
+pub fn decode(bytecode: &[u32]) -> Result<Vec<Instruction<'_>>, DecodeError> {
+    let mut instructions = Vec::new();
+    let mut location = 0;
+
+    while location < bytecode.len() {
+        let opcode = Opcode::try_from_primitive(bytecode[location]).map_err(|_| {
+            DecodeError::InvalidOpcode {
+                location,
+                opcode: bytecode[location],
+            }
+        })?;
+
+        let start = location + 1;
+        let end = start + opcode.immediate_count();
+        let immediates = bytecode
+            .get(start..end)
+            .ok_or(DecodeError::Truncated { location, opcode })?;
+
+        instructions.push(Instruction {
+            location,
+            opcode,
+            immediates,
+        });
+        location = end;
+    }
+
+    Ok(instructions)
+}
+
+/// Writes a listing of the program's constants, events and data, followed by every
+/// procedure grouped by the target that owns it.
+pub fn disassemble(program: &Program, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "Constants:")?;
+    for (idx, constant) in program.constants.iter().enumerate() {
+        writeln!(writer, "{idx:>5}: {}", describe_value(program, constant))?;
+    }
+
+    writeln!(writer, "\nEvents:")?;
+    for (idx, event) in program.events.iter().enumerate() {
+        writeln!(writer, "{idx:>5}: {:?}", event.name())?;
+    }
+
+    writeln!(writer, "\nGlobal Variables:")?;
+    for (idx, var) in program.global_vars.iter().enumerate() {
+        writeln!(writer, "{idx:>5}: {}", var.name)?;
+    }
+
+    writeln!(writer, "\nGlobal Lists:")?;
+    for (idx, list) in program.global_lists.iter().enumerate() {
+        writeln!(writer, "{idx:>5}: {}", list.name)?;
+    }
+
+    for (target_id, target) in program.targets.iter().enumerate() {
+        writeln!(writer, "\nTarget {target_id} {:?}:", target.name())?;
+
+        // Target data is numbered after the globals, which is how bytecode refers to it
+        writeln!(writer, "    Variables:")?;
+        for (idx, var) in target.vars.iter().enumerate() {
+            let idx = idx + program.global_vars.len();
+            writeln!(writer, "{idx:>9}: {}", var.name)?;
+        }
+
+        writeln!(writer, "    Lists:")?;
+        for (idx, list) in target.lists.iter().enumerate() {
+            let idx = idx + program.global_lists.len();
+            writeln!(writer, "{idx:>9}: {}", list.name)?;
+        }
+
+        for procedure in &program.procedures {
+            if procedure.target_id == target_id {
+                writeln!(writer)?;
+                disassemble_procedure(program, procedure, writer)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a listing of a single procedure. Jump targets are given labels, immediates that
+/// refer to the program's constants, data, events and builtins are annotated with what
+/// they refer to, and each run of instructions is preceded by the block it came from.
+pub fn disassemble_procedure(
+    program: &Program,
+    procedure: &ProcedureValue,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(writer, "    Procedure {} {:?}:", procedure.id().get(), procedure.name())?;
+
+    // Triggers are sorted since they're stored in a hash map
+    let mut triggers = program
+        .triggers
+        .iter()
+        .filter(|(_, procs)| procs.iter().any(|proc| proc.id() == procedure.id()))
+        .map(|(trigger, _)| describe_trigger(program, trigger))
+        .collect::<Vec<_>>();
+    triggers.sort();
+    if !triggers.is_empty() {
+        writeln!(writer, "        Triggers: {}", triggers.join(", "))?;
+    }
+    if procedure.warp {
+        writeln!(writer, "        Warp: true")?;
+    }
+
+    writeln!(writer, "        Locals:")?;
+    for (idx, local) in procedure.locals.iter().enumerate() {
+        let kind = if idx < procedure.param_count { "param " } else { "" };
+        writeln!(writer, "{idx:>13}: {kind}{}", local.name().unwrap_or("{unnamed}"))?;
+    }
+    writeln!(writer)?;
+
+    let instructions = match decode(procedure.bytecode()) {
+        Ok(instructions) => instructions,
+        Err(err) => return writeln!(writer, "        !! {err}"),
+    };
+
+    // Labels are numbered in the order they appear in the bytecode
+    let labels = instructions
+        .iter()
+        .filter_map(Instruction::jump_target)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(idx, location)| (location, format!("L{idx}")))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut current_block = None;
+
+    for instruction in &instructions {
+        if let Some(label) = labels.get(&instruction.location) {
+            writeln!(writer, "      {label}:")?;
+        }
+
+        let block = procedure.source_map().block_at(instruction.location);
+        if block != current_block {
+            current_block = block;
+            if let Some(block) = block {
+                writeln!(writer, "        // {} {:?}", block.opcode, block.id)?;
+            }
+        }
+
+        let (text, comment) = format_instruction(program, procedure, instruction, &labels);
+        if let Some(comment) = comment {
+            writeln!(writer, "        {:06}    {text:<23} // {comment}", instruction.location)?;
+        } else {
+            writeln!(writer, "        {:06}    {text}", instruction.location)?;
+        }
+    }
+
+    // A jump past the last instruction still deserves a label
+    if let Some(label) = labels.get(&procedure.bytecode().len()) {
+        writeln!(writer, "      {label}:")?;
+    }
+
+    Ok(())
+}
+
+/// Formats an instruction and an optional comment describing its immediates.
+fn format_instruction(
+    program: &Program,
+    procedure: &ProcedureValue,
+    instruction: &Instruction,
+    labels: &BTreeMap<usize, String>,
+) -> (String, Option<String>) {
+    let mut text = format!("{:?}", instruction.opcode);
+
+    if let Some(location) = instruction.jump_target() {
+        write!(text, " {}", labels[&location]).unwrap();
+        return (text, None);
+    }
+
+    for (idx, imm) in instruction.immediates.iter().enumerate() {
+        let separator = if idx == 0 { " " } else { ", " };
+        write!(text, "{separator}{imm}").unwrap();
+    }
+
+    let Some(&imm) = instruction.immediates.first() else {
+        return (text, None);
+    };
+    let idx = imm as usize;
+
+    let comment = match instruction.opcode {
+        Opcode::PushConstant => program
+            .constants
+            .get(idx)
+            .map(|constant| describe_value(program, constant)),
+        Opcode::PushNumber => {
+            let bytes = bytemuck::cast([instruction.immediates[0], instruction.immediates[1]]);
+            Some(format!("number {}", f64::from_le_bytes(bytes)))
+        }
+        Opcode::PushVar
+        | Opcode::SetVar
+        | Opcode::DecVar
+        | Opcode::ZeroVar
+        | Opcode::ClearVar
+        | Opcode::ChangeVar => {
+            let target = &program.targets[procedure.target_id];
+            match idx.checked_sub(program.global_vars.len()) {
+                Some(idx) => target.vars.get(idx).map(|var| format!("Target {}", var.name)),
+                None => Some(format!("Global {}", program.global_vars[idx].name)),
+            }
+        }
+        Opcode::PushList
+        | Opcode::ListAdd
+        | Opcode::ListDelete
+        | Opcode::ListDeleteAll
+        | Opcode::ListInsert
+        | Opcode::ListReplace
+        | Opcode::ListItem
+        | Opcode::ListLength
+        | Opcode::ListContains
+        | Opcode::ListIndexOf => {
+            let target = &program.targets[procedure.target_id];
+            match idx.checked_sub(program.global_lists.len()) {
+                Some(idx) => target.lists.get(idx).map(|list| format!("Target list {}", list.name)),
+                None => Some(format!("Global list {}", program.global_lists[idx].name)),
+            }
+        }
+        Opcode::PushLocal
+        | Opcode::SetLocal
+        | Opcode::DecLocal
+        | Opcode::ZeroLocal
+        | Opcode::ClearLocal => procedure.locals.get(idx).map(|local| {
+            let kind = if idx < procedure.param_count { "param " } else { "" };
+            format!("Local {kind}{}", local.name().unwrap_or("{unnamed}"))
+        }),
+        Opcode::MathOp => MathOp::try_from_primitive(imm).ok().map(|op| format!("{op:?}")),
+        Opcode::DispatchEvent => program
+            .events
+            .get(idx)
+            .map(|event| format!("event {:?}", event.name())),
+        Opcode::CallBuiltin => program
+            .builtins
+            .as_ref()
+            .and_then(|library| library.opcode(idx))
+            .map(str::to_owned),
+        Opcode::CallProcedure => program
+            .procedures
+            .get(idx)
+            .map(|proc| format!("procedure {:?}", proc.name())),
+        _ => return (text, None),
+    };
+
+    (text, Some(comment.unwrap_or_else(|| "{unknown}".to_owned())))
+}
+
+fn describe_value(program: &Program, value: &Value) -> String {
+    match value {
+        Value::String(string) => format!("string {string:?}"),
+        Value::Number(num) => format!("number {num}"),
+        Value::Boolean(bool) => format!("boolean {bool}"),
+        other => program.dbg_string(other).to_string(),
+    }
+}
+
+fn describe_trigger(program: &Program, trigger: &Trigger) -> String {
+    match trigger {
+        Trigger::OnStart => "green flag".to_owned(),
+        Trigger::Event(id) => {
+            let name = program.events.get(id.get()).map_or("{unknown}", |e| e.name());
+            format!("broadcast {name:?}")
+        }
+        Trigger::BackdropSwitchedTo(name) => format!("backdrop {name:?}"),
+        Trigger::CloneStarted => "clone started".to_owned(),
+        Trigger::KeyPressed(key) => format!("key {key:?}"),
+        Trigger::SpriteClicked => "sprite clicked".to_owned(),
+        Trigger::StageClicked => "stage clicked".to_owned(),
+        Trigger::EdgeActivated => "every frame".to_owned(),
+    }
+}
@@ -0,0 +1,734 @@
+//! A binary format for compiled programs, so that a project can be compiled once and
+//! loaded many times without going through the project JSON again.
+//!
+//! An image starts with [`MAGIC`] and a little-endian [`VERSION`], followed by the
+//! program's constants, events, global data, targets, procedures and triggers. Integers
+//! are little-endian `u32`s, numbers are `f64`s, and strings are prefixed by their byte
+//! length. Builtins are stored as a table of block opcodes, and they're rebound by name
+//! when the image is loaded so that images survive blocks being added to the library.
+//! Each procedure's source map is kept so that errors can still be traced to blocks.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use derive_more::{Display, Error, From};
+use num_enum::TryFromPrimitive;
+
+use crate::{
+    ast::{Costume, RotationStyle, Sound},
+    blocks::BlockLibrary,
+    interpreter::{
+        Program, TargetScope,
+        disasm::{DecodeError, decode},
+        opcode::{MathOp, Opcode, Trigger},
+        source_map::{BlockSource, SourceMap},
+        sprite::{GraphicEffect, GraphicEffects, SpriteState},
+        value::{EventValue, ListState, Local, ProcedureValue, Value, VarState},
+    },
+};
+
+/// The bytes every image starts with.
+pub const MAGIC: [u8; 4] = *b"SVMI";
+/// The version of the image format. It must be bumped whenever the layout of an image or
+/// the meaning of bytecode changes.
+pub const VERSION: u32 = 5;
+
+#[derive(Debug, Display, Error, From)]
+pub enum ImageError {
+    #[display("failed to read image: {_0}")]
+    Io(io::Error),
+    #[display("file is not a program image")]
+    #[from(skip)]
+    BadMagic,
+    #[display("image version {_0} is not supported (expected {VERSION})")]
+    #[from(skip)]
+    UnsupportedVersion(#[error(not(source))] u32),
+    #[display("image is malformed: {_0}")]
+    #[from(skip)]
+    Malformed(#[error(not(source))] &'static str),
+    #[display("image uses block {_0:?}, which isn't in the block library")]
+    #[from(skip)]
+    MissingBuiltin(#[error(not(source))] Arc<str>),
+    #[display("image contains invalid bytecode: {_0}")]
+    InvalidBytecode(DecodeError),
+}
+
+/// Writes the compiled state of a program as an image. Variables and lists are saved
+/// with their current values, so this should be done before the program starts running.
+/// Programs with clones can't be saved, since clones only exist while a program runs.
+pub fn write_image(program: &Program, writer: &mut impl Write) -> io::Result<()> {
+    if program.targets.iter().any(|target| target.is_clone()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "images can't contain clones",
+        ));
+    }
+
+    let Some(builtins) = &program.builtins else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the program's builtins are in use by a running block",
+        ));
+    };
+
+    let mut w = ImageWriter(writer);
+
+    w.bytes(&MAGIC)?;
+    w.u32(VERSION)?;
+
+    w.len(builtins.len())?;
+    for idx in 0..builtins.len() {
+        w.str(builtins.opcode(idx).unwrap())?;
+    }
+
+    w.len(program.constants.len())?;
+    for constant in &program.constants {
+        w.value(constant)?;
+    }
+
+    w.len(program.events.len())?;
+    for event in &program.events {
+        w.str(event.name())?;
+    }
+
+    w.vars(&program.global_vars)?;
+    w.lists(&program.global_lists)?;
+
+    w.len(program.targets.len())?;
+    for target in &program.targets {
+        w.target(target)?;
+    }
+    w.len(program.layers.len())?;
+    for &id in &program.layers {
+        w.len(id)?;
+    }
+
+    w.len(program.procedures.len())?;
+    for procedure in &program.procedures {
+        w.procedure(procedure)?;
+    }
+
+    // Each procedure has at most one trigger, so ordering by the first handler makes
+    // images of the same program byte-for-byte identical.
+    let mut triggers = program.triggers.iter().collect::<Vec<_>>();
+    triggers.sort_by_key(|(_, procs)| procs.first().map(|proc| proc.id().get()));
+    w.len(triggers.len())?;
+    for (trigger, procs) in triggers {
+        w.trigger(trigger)?;
+        w.len(procs.len())?;
+        for proc in procs {
+            w.len(proc.id().get())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a program from an image, binding its builtins to the blocks in the library with
+/// the same opcodes.
+pub fn read_image(reader: &mut impl Read, library: BlockLibrary) -> Result<Program, ImageError> {
+    let mut r = ImageReader(reader);
+
+    if r.array::<4>()? != MAGIC {
+        return Err(ImageError::BadMagic);
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+
+    let opcodes = r.list(|r| r.str())?;
+    let (_, builtins) = library.split();
+    let builtins = builtins.rebind(&opcodes);
+
+    let constants = r.list(|r| r.value())?.into_boxed_slice();
+    let events = r.list(|r| Ok(EventValue::new(r.str()?)))?;
+    let global_vars = r.vars()?;
+    let global_lists = r.lists()?;
+    let targets = r.list(|r| r.target())?;
+    let layers = r.list(|r| r.index(targets.len()))?;
+    if layers.iter().collect::<HashSet<_>>().len() != targets.len() {
+        return Err(ImageError::Malformed("layers don't cover every target"));
+    }
+    if layers.first().is_some_and(|&id| !targets[id].is_stage()) {
+        return Err(ImageError::Malformed("stage isn't the back layer"));
+    }
+
+    let procedures = r.list(|r| r.procedure(targets.len()))?;
+    for procedure in &procedures {
+        let target = &targets[procedure.target_id];
+        let instructions = decode(procedure.bytecode())?;
+        let locations = instructions
+            .iter()
+            .map(|instruction| instruction.location)
+            .chain([procedure.bytecode().len()])
+            .collect::<HashSet<_>>();
+
+        // Everything an instruction refers to must exist, so that a corrupt image fails
+        // to load rather than panicking once it runs
+        for instruction in &instructions {
+            let Some(&imm) = instruction.immediates.first() else {
+                continue;
+            };
+            let idx = imm as usize;
+
+            let error = match instruction.opcode {
+                Opcode::CallBuiltin if !builtins.contains(idx) => {
+                    let opcode = opcodes.get(idx).cloned().unwrap_or_else(|| "{unknown}".into());
+                    return Err(ImageError::MissingBuiltin(opcode));
+                }
+                Opcode::CallProcedure if idx >= procedures.len() => "call to a missing procedure",
+                Opcode::PushConstant if idx >= constants.len() => "missing constant",
+                Opcode::DispatchEvent if idx >= events.len() => "broadcast of a missing event",
+                Opcode::MathOp if MathOp::try_from_primitive(imm).is_err() => "invalid math op",
+                Opcode::PushVar
+                | Opcode::SetVar
+                | Opcode::DecVar
+                | Opcode::ZeroVar
+                | Opcode::ClearVar
+                | Opcode::ChangeVar
+                    if idx >= global_vars.len() + target.vars.len() =>
+                {
+                    "missing variable"
+                }
+                Opcode::PushList
+                | Opcode::ListAdd
+                | Opcode::ListDelete
+                | Opcode::ListDeleteAll
+                | Opcode::ListInsert
+                | Opcode::ListReplace
+                | Opcode::ListItem
+                | Opcode::ListLength
+                | Opcode::ListContains
+                | Opcode::ListIndexOf
+                    if idx >= global_lists.len() + target.lists.len() =>
+                {
+                    "missing list"
+                }
+                Opcode::PushLocal
+                | Opcode::SetLocal
+                | Opcode::DecLocal
+                | Opcode::ZeroLocal
+                | Opcode::ClearLocal
+                    if idx >= procedure.locals.len() =>
+                {
+                    "missing local"
+                }
+                Opcode::Jump | Opcode::JumpIfTrue | Opcode::JumpIfFalse
+                    if !locations.contains(&idx) =>
+                {
+                    "jump to the middle of an instruction"
+                }
+                _ => continue,
+            };
+            return Err(ImageError::Malformed(error));
+        }
+    }
+
+    let mut program = Program::new(builtins, constants, events, global_vars, global_lists, targets);
+    program.set_layers(layers);
+
+    let procedures = procedures
+        .into_iter()
+        .map(|procedure| program.register(procedure))
+        .collect::<Vec<_>>();
+
+    let trigger_count = r.len()?;
+    for _ in 0..trigger_count {
+        let trigger = r.trigger(program.events.len())?;
+        let handlers = r.list(|r| r.index(procedures.len()))?;
+        for idx in handlers {
+            program.add_trigger(procedures[idx].clone(), trigger.clone());
+        }
+    }
+
+    Ok(program)
+}
+
+struct ImageWriter<'a, W>(&'a mut W);
+
+impl<W: Write> ImageWriter<'_, W> {
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+
+    fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.bytes(&[value])
+    }
+
+    fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        let len = u32::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "program is too large"))?;
+        self.u32(len)
+    }
+
+    fn f64(&mut self, value: f64) -> io::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn bool(&mut self, value: bool) -> io::Result<()> {
+        self.u8(value.into())
+    }
+
+    fn str(&mut self, string: &str) -> io::Result<()> {
+        self.len(string.len())?;
+        self.bytes(string.as_bytes())
+    }
+
+    fn value(&mut self, value: &Value) -> io::Result<()> {
+        match value {
+            Value::String(string) => {
+                self.u8(0)?;
+                self.str(string)
+            }
+            &Value::Number(num) => {
+                self.u8(1)?;
+                self.f64(num)
+            }
+            &Value::Boolean(bool) => {
+                self.u8(2)?;
+                self.bool(bool)
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{other:?} can't be stored in an image"),
+            )),
+        }
+    }
+
+    fn vars(&mut self, vars: &[VarState]) -> io::Result<()> {
+        self.len(vars.len())?;
+        for var in vars {
+            self.str(&var.name)?;
+            self.value(&var.value.borrow())?;
+        }
+        Ok(())
+    }
+
+    fn lists(&mut self, lists: &[ListState]) -> io::Result<()> {
+        self.len(lists.len())?;
+        for list in lists {
+            let items = list.items.borrow();
+            self.str(&list.name)?;
+            self.len(items.len())?;
+            for item in items.iter() {
+                self.value(item)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn target(&mut self, target: &TargetScope) -> io::Result<()> {
+        self.str(&target.name)?;
+        self.vars(&target.vars)?;
+        self.lists(&target.lists)?;
+
+        self.len(target.costumes.len())?;
+        for costume in target.costumes.iter() {
+            self.str(&costume.name)?;
+            self.str(&costume.asset_id)?;
+            self.str(&costume.md5ext)?;
+            self.f64(costume.bitmap_resolution)?;
+            self.f64(costume.rotation_center.0)?;
+            self.f64(costume.rotation_center.1)?;
+        }
+        self.len(target.current_costume)?;
+
+        self.len(target.sounds.len())?;
+        for sound in target.sounds.iter() {
+            self.str(&sound.name)?;
+            self.str(&sound.asset_id)?;
+            self.str(&sound.md5ext)?;
+            self.u32(sound.rate)?;
+            self.u32(sound.sample_count)?;
+        }
+
+        for effect in GraphicEffect::ALL {
+            self.f64(target.effects.get(effect))?;
+        }
+
+        // Bubbles belong to running tasks, so they aren't saved
+        match &target.sprite {
+            Some(sprite) => {
+                self.bool(true)?;
+                self.f64(sprite.x)?;
+                self.f64(sprite.y)?;
+                self.f64(sprite.direction)?;
+                self.f64(sprite.size)?;
+                self.bool(sprite.visible)?;
+                self.bool(sprite.draggable)?;
+                self.str(sprite.rotation_style.name())
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn procedure(&mut self, procedure: &ProcedureValue) -> io::Result<()> {
+        self.bool(procedure.name.is_some())?;
+        if let Some(name) = &procedure.name {
+            self.str(name)?;
+        }
+        self.len(procedure.target_id)?;
+        self.len(procedure.param_count)?;
+        self.bool(procedure.warp)?;
+
+        self.len(procedure.locals.len())?;
+        for local in &procedure.locals {
+            self.bool(local.name().is_some())?;
+            if let Some(name) = local.name() {
+                self.str(name)?;
+            }
+        }
+
+        self.len(procedure.bytecode().len())?;
+        for &word in procedure.bytecode() {
+            self.u32(word)?;
+        }
+
+        let spans = procedure.source_map().spans();
+        self.len(spans.len())?;
+        for span in spans {
+            self.len(span.range.start)?;
+            self.len(span.range.end)?;
+            self.str(&span.block.id)?;
+            self.str(&span.block.opcode)?;
+        }
+        Ok(())
+    }
+
+    fn trigger(&mut self, trigger: &Trigger) -> io::Result<()> {
+        match trigger {
+            Trigger::OnStart => self.u8(0),
+            Trigger::Event(id) => {
+                self.u8(1)?;
+                self.len(id.get())
+            }
+            Trigger::BackdropSwitchedTo(name) => {
+                self.u8(2)?;
+                self.str(name)
+            }
+            Trigger::CloneStarted => self.u8(3),
+            Trigger::KeyPressed(key) => {
+                self.u8(4)?;
+                self.str(key)
+            }
+            Trigger::SpriteClicked => self.u8(5),
+            Trigger::StageClicked => self.u8(6),
+            Trigger::EdgeActivated => self.u8(7),
+        }
+    }
+}
+
+struct ImageReader<'a, R>(&'a mut R);
+
+impl<R: Read> ImageReader<'_, R> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ImageError> {
+        let mut bytes = [0; N];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ImageError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, ImageError> {
+        Ok(self.u32()? as usize)
+    }
+
+    /// Reads an index that must be less than `count`.
+    fn index(&mut self, count: usize) -> Result<usize, ImageError> {
+        let idx = self.len()?;
+        if idx >= count {
+            return Err(ImageError::Malformed("index out of bounds"));
+        }
+        Ok(idx)
+    }
+
+    fn f64(&mut self) -> Result<f64, ImageError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn bool(&mut self) -> Result<bool, ImageError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ImageError::Malformed("invalid boolean")),
+        }
+    }
+
+    fn str(&mut self) -> Result<Arc<str>, ImageError> {
+        let len = self.len()?;
+        let mut bytes = Vec::new();
+        // Reading through `take` stops a corrupt length from allocating a huge buffer
+        self.0.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let string = String::from_utf8(bytes).map_err(|_| ImageError::Malformed("invalid UTF-8"))?;
+        Ok(string.into())
+    }
+
+    fn list<T>(
+        &mut self,
+        mut read_item: impl FnMut(&mut Self) -> Result<T, ImageError>,
+    ) -> Result<Vec<T>, ImageError> {
+        let len = self.len()?;
+        let mut items = Vec::new();
+        for _ in 0..len {
+            items.push(read_item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn value(&mut self) -> Result<Value, ImageError> {
+        Ok(match self.u8()? {
+            0 => Value::String(self.str()?),
+            1 => Value::Number(self.f64()?),
+            2 => Value::Boolean(self.bool()?),
+            _ => return Err(ImageError::Malformed("invalid value")),
+        })
+    }
+
+    fn vars(&mut self) -> Result<Vec<VarState>, ImageError> {
+        self.list(|r| {
+            Ok(VarState {
+                name: r.str()?,
+                value: r.value()?.into(),
+            })
+        })
+    }
+
+    fn lists(&mut self) -> Result<Vec<ListState>, ImageError> {
+        self.list(|r| {
+            Ok(ListState {
+                name: r.str()?,
+                items: r.list(|r| r.value())?.into(),
+            })
+        })
+    }
+
+    fn target(&mut self) -> Result<TargetScope, ImageError> {
+        let mut target = TargetScope::new(self.str()?, self.vars()?, self.lists()?);
+
+        let costumes = self.list(|r| {
+            Ok(Costume {
+                name: r.str()?,
+                asset_id: r.str()?,
+                md5ext: r.str()?,
+                bitmap_resolution: r.f64()?,
+                rotation_center: (r.f64()?, r.f64()?),
+            })
+        })?;
+        target.current_costume = self.len()?;
+        if target.current_costume >= costumes.len().max(1) {
+            return Err(ImageError::Malformed("current costume out of bounds"));
+        }
+        target.costumes = costumes.into();
+
+        let sounds = self.list(|r| {
+            Ok(Sound {
+                name: r.str()?,
+                asset_id: r.str()?,
+                md5ext: r.str()?,
+                rate: r.u32()?,
+                sample_count: r.u32()?,
+            })
+        })?;
+        target.sounds = sounds.into();
+
+        let mut effects = GraphicEffects::default();
+        for effect in GraphicEffect::ALL {
+            effects.set(effect, self.f64()?);
+        }
+        target.effects = effects;
+
+        if self.bool()? {
+            target.sprite = Some(SpriteState {
+                x: self.f64()?,
+                y: self.f64()?,
+                direction: self.f64()?,
+                size: self.f64()?,
+                visible: self.bool()?,
+                draggable: self.bool()?,
+                rotation_style: RotationStyle::from_name(&self.str()?)
+                    .ok_or(ImageError::Malformed("invalid rotation style"))?,
+                bubble: None,
+            });
+        }
+
+        Ok(target)
+    }
+
+    fn procedure(&mut self, target_count: usize) -> Result<ProcedureValue, ImageError> {
+        let name = if self.bool()? { Some(self.str()?) } else { None };
+        let target_id = self.index(target_count)?;
+        let param_count = self.len()?;
+        let warp = self.bool()?;
+
+        let locals = self.list(|r| {
+            let name = if r.bool()? { Some(r.str()?) } else { None };
+            Ok(Local::new(name))
+        })?;
+        if param_count > locals.len() {
+            return Err(ImageError::Malformed("procedure has more params than locals"));
+        }
+
+        let bytecode = self.list(|r| r.u32())?;
+
+        let mut source_map = SourceMap::new();
+        let span_count = self.len()?;
+        for _ in 0..span_count {
+            let start = self.len()?;
+            let end = self.len()?;
+            if start > end || end > bytecode.len() {
+                return Err(ImageError::Malformed("source span out of bounds"));
+            }
+
+            let block = BlockSource {
+                id: self.str()?,
+                opcode: self.str()?,
+            };
+            source_map.insert(start..end, block);
+        }
+
+        let procedure = ProcedureValue::new(
+            name,
+            target_id,
+            param_count,
+            locals.into_boxed_slice(),
+            bytecode.into_boxed_slice(),
+            warp,
+        );
+        Ok(procedure.with_source_map(source_map))
+    }
+
+    fn trigger(&mut self, event_count: usize) -> Result<Trigger, ImageError> {
+        Ok(match self.u8()? {
+            0 => Trigger::OnStart,
+            1 => Trigger::Event(self.index(event_count)?.into()),
+            2 => Trigger::BackdropSwitchedTo(self.str()?),
+            3 => Trigger::CloneStarted,
+            4 => Trigger::KeyPressed(self.str()?),
+            5 => Trigger::SpriteClicked,
+            6 => Trigger::StageClicked,
+            7 => Trigger::EdgeActivated,
+            _ => return Err(ImageError::Malformed("invalid trigger")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{ImageError, MAGIC, VERSION, read_image, write_image};
+    use crate::{
+        ast::project::ScratchProject,
+        blocks::BlockLibrary,
+        interpreter::{
+            Program,
+            opcode::Opcode,
+            value::{Local, ProcedureValue},
+        },
+        sb3::Sb3Project,
+    };
+
+    fn compile_counter() -> Program {
+        let sb3: Sb3Project = serde_json::from_str(include_str!("../../test/counter.json")).unwrap();
+        ScratchProject::try_from(sb3).unwrap().compile()
+    }
+
+    fn image_of(program: &Program) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_image(program, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn load(bytes: &[u8]) -> Result<Program, ImageError> {
+        read_image(&mut &bytes[..], BlockLibrary::default())
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = image_of(&compile_counter());
+        let program = load(&bytes).unwrap();
+
+        assert_eq!(image_of(&program), bytes);
+    }
+
+    #[test]
+    fn bad_header() {
+        let bytes = image_of(&compile_counter());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] ^= 0xFF;
+        assert!(matches!(load(&bad_magic), Err(ImageError::BadMagic)));
+
+        let mut old_version = bytes.clone();
+        old_version[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        assert!(matches!(
+            load(&old_version),
+            Err(ImageError::UnsupportedVersion(version)) if version == VERSION - 1
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = image_of(&compile_counter());
+
+        for len in 0..bytes.len() {
+            assert!(load(&bytes[..len]).is_err(), "image cut to {len} bytes loaded");
+        }
+    }
+
+    #[test]
+    fn invalid_bytecode() {
+        let cases = [
+            vec![Opcode::PushConstant as u32, 1000],
+            vec![Opcode::PushVar as u32, 1000],
+            vec![Opcode::PushList as u32, 1000],
+            vec![Opcode::PushLocal as u32, 0],
+            vec![Opcode::DispatchEvent as u32, 1000],
+            vec![Opcode::CallProcedure as u32, 1000],
+            vec![Opcode::MathOp as u32, 1000],
+            vec![Opcode::Jump as u32, 1],
+            vec![Opcode::Jump as u32, 3],
+            vec![Opcode::PushConstant as u32],
+            vec![u32::MAX],
+        ];
+
+        for bytecode in cases {
+            let mut program = compile_counter();
+            let procedure = ProcedureValue::new(
+                None,
+                0,
+                0,
+                Box::<[Local]>::default(),
+                bytecode.clone().into_boxed_slice(),
+                false,
+            );
+            program.register(Rc::new(procedure));
+
+            let bytes = image_of(&program);
+            assert!(
+                matches!(
+                    load(&bytes),
+                    Err(ImageError::Malformed(_) | ImageError::InvalidBytecode(_))
+                ),
+                "{bytecode:?} wasn't rejected as invalid"
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, fmt::Debug, sync::Arc};
+
+use crate::interpreter::{Task, id::Id, value::Value};
+
+/// The state of the mouse and keyboard that sensing blocks read. Nothing is pressed until
+/// the application embedding the interpreter says so.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    mouse_position: (f64, f64),
+    mouse_down: bool,
+    /// The keys that are held down, named like Scratch names them.
+    pressed_keys: Vec<Arc<str>>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The position of the mouse pointer in stage coordinates.
+    pub fn mouse_position(&self) -> (f64, f64) {
+        self.mouse_position
+    }
+
+    pub fn set_mouse_position(&mut self, x: f64, y: f64) {
+        self.mouse_position = (x, y);
+    }
+
+    pub fn is_mouse_down(&self) -> bool {
+        self.mouse_down
+    }
+
+    pub fn set_mouse_down(&mut self, down: bool) {
+        self.mouse_down = down;
+    }
+
+    /// Marks a key as held down. Keys are named like in Scratch's key menus, such as `a`,
+    /// `5`, `space` or `left arrow`.
+    pub fn press_key(&mut self, key: &str) {
+        let key = scratch_key(&Value::from(key));
+        if !key.is_empty() && !self.pressed_keys.contains(&key) {
+            self.pressed_keys.push(key);
+        }
+    }
+
+    pub fn release_key(&mut self, key: &str) {
+        let key = scratch_key(&Value::from(key));
+        self.pressed_keys.retain(|pressed| *pressed != key);
+    }
+
+    pub fn pressed_keys(&self) -> &[Arc<str>] {
+        &self.pressed_keys
+    }
+
+    /// Whether a key is held down, where `any` matches every key. Like in Scratch, keys
+    /// can also be given as key codes or as text whose first character is the key.
+    pub fn is_key_pressed(&self, key: &Value) -> bool {
+        if matches!(key, Value::String(key) if &**key == "any") {
+            return !self.pressed_keys.is_empty();
+        }
+
+        let key = scratch_key(key);
+        self.pressed_keys.contains(&key)
+    }
+}
+
+/// Names of keys that are longer than a single character.
+const SPECIAL_KEYS: [&str; 6] = [
+    "space",
+    "left arrow",
+    "up arrow",
+    "right arrow",
+    "down arrow",
+    "enter",
+];
+
+/// Converts a key argument to the name Scratch uses for the key. Key codes for digits,
+/// letters, space and the arrow keys are accepted, as are the names of special keys.
+/// Other text is reduced to its first character in upper case, and empty text stays empty
+/// so that it never matches a key.
+pub fn scratch_key(key: &Value) -> Arc<str> {
+    if let &Value::Number(code) = key {
+        match code {
+            48.0..=90.0 => return char::from(code as u8).to_string().into(),
+            32.0 => return "space".into(),
+            37.0 => return "left arrow".into(),
+            38.0 => return "up arrow".into(),
+            39.0 => return "right arrow".into(),
+            40.0 => return "down arrow".into(),
+            _ => {}
+        }
+    }
+
+    let key = key.cast_string();
+    if SPECIAL_KEYS.contains(&&*key) {
+        return key;
+    }
+
+    match key.chars().next() {
+        Some(' ') => "space".into(),
+        Some(char) => char.to_uppercase().collect::<String>().into(),
+        None => "".into(),
+    }
+}
+
+/// Answers the questions asked by "ask and wait" blocks.
+pub trait AnswerSource: Debug {
+    /// Answers a question, or returns `None` if there's no answer yet, in which case
+    /// the question is asked again on the next frame.
+    fn answer(&mut self, question: &str) -> Option<Arc<str>>;
+}
+
+/// Gives a fixed list of answers in order, then answers everything else with empty text.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedAnswers {
+    answers: VecDeque<Arc<str>>,
+}
+
+impl ScriptedAnswers {
+    pub fn new(answers: impl IntoIterator<Item = impl Into<Arc<str>>>) -> Self {
+        Self {
+            answers: answers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl AnswerSource for ScriptedAnswers {
+    fn answer(&mut self, _question: &str) -> Option<Arc<str>> {
+        Some(self.answers.pop_front().unwrap_or_default())
+    }
+}
+
+/// A question waiting to be answered, asked by a task running "ask and wait".
+#[derive(Debug, Clone)]
+pub(super) struct Question {
+    pub task: Id<Task>,
+    pub target_id: usize,
+    pub text: Arc<str>,
+    /// Whether the question is said by its sprite, which is only the case for sprites that
+    /// were visible when they asked.
+    pub shows_bubble: bool,
+}
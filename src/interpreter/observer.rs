@@ -0,0 +1,65 @@
+use std::fmt::Debug;
+
+use crate::{
+    ast::Sound,
+    interpreter::{
+        Task, TargetScope,
+        id::Id,
+        source_map::BlockSource,
+        sprite::Bubble,
+        value::{EventValue, ListState, VarState},
+    },
+};
+
+/// Receives notifications about changes to a [`Program`](super::Program)'s state, so that
+/// applications embedding the interpreter can react to them. Every method does nothing
+/// by default.
+///
+/// Targets are identified by their index in [`Program::targets`](super::Program::targets).
+/// Global variables and lists are reported as belonging to the stage.
+pub trait ProgramObserver: Debug {
+    /// A frame is about to run. Frames are numbered from 1.
+    fn frame_started(&mut self, _frame: u64) {}
+
+    /// Every task that was scheduled for the frame has run until it yielded or finished.
+    fn frame_finished(&mut self, _frame: u64) {}
+
+    /// A task was scheduled to run, such as by a hat block being triggered.
+    fn task_started(&mut self, _task: &Task) {}
+
+    /// A task ran to the end of its script.
+    fn task_finished(&mut self, _task: &Task) {}
+
+    /// A task panicked while running the given block, or outside of any block if it's
+    /// `None`. The panic is passed on once every observer has been notified.
+    fn task_panicked(&mut self, _task: &Task, _block: Option<&BlockSource>) {}
+
+    /// A broadcast was sent by a script.
+    fn broadcast_sent(&mut self, _event: Id<EventValue>, _name: &str) {}
+
+    /// A variable was set or changed, even if its value stayed the same.
+    fn variable_changed(&mut self, _target_id: usize, _variable: &VarState) {}
+
+    /// A list was modified, even if its contents stayed the same.
+    fn list_changed(&mut self, _target_id: usize, _list: &ListState) {}
+
+    /// A block modified a target's position, direction, size, visibility, costume,
+    /// effects or layer. This is reported once for each block that modifies a target.
+    fn target_changed(&mut self, _target_id: usize, _target: &TargetScope) {}
+
+    /// A sprite's speech bubble was shown, replaced, or cleared (when `bubble` is `None`).
+    fn bubble_changed(&mut self, _target_id: usize, _bubble: Option<&Bubble>) {}
+
+    /// A clone was created from a sprite or another clone. The clone's id may be that of
+    /// a clone that was deleted earlier.
+    fn clone_created(&mut self, _target_id: usize, _parent_id: usize) {}
+
+    /// A clone was deleted, so its id no longer refers to a target until it's reused.
+    fn clone_deleted(&mut self, _target_id: usize) {}
+
+    /// A question from an "ask and wait" block is being shown, waiting for an answer.
+    fn question_asked(&mut self, _target_id: usize, _question: &str) {}
+
+    /// A target started playing a sound.
+    fn sound_played(&mut self, _target_id: usize, _sound: &Sound) {}
+}
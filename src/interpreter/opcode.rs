@@ -1,3 +1,5 @@
+use std::{f64::consts::LN_10, sync::Arc};
+
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use crate::interpreter::{id::Id, value::EventValue};
@@ -20,6 +22,17 @@ pub enum Opcode {
     ZeroLocal,
     ClearLocal,
 
+    PushList,
+    ListAdd,
+    ListDelete,
+    ListDeleteAll,
+    ListInsert,
+    ListReplace,
+    ListItem,
+    ListLength,
+    ListContains,
+    ListIndexOf,
+
     PushZero,
     PushConstant,
     PushUInt32,
@@ -27,8 +40,19 @@ pub enum Opcode {
     PeekStack,
 
     Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Round,
+    MathOp,
 
     GreaterThan,
+    LessThan,
+    Equals,
+    And,
+    Or,
+    Not,
 
     DispatchEvent,
     CallBuiltin,
@@ -41,6 +65,139 @@ pub enum Opcode {
     Sleep,
 }
 
+impl Opcode {
+    /// The number of immediates that follow this opcode in the bytecode.
+    pub const fn immediate_count(self) -> usize {
+        match self {
+            Self::PushVar
+            | Self::SetVar
+            | Self::DecVar
+            | Self::ZeroVar
+            | Self::ClearVar
+            | Self::ChangeVar
+            | Self::PushLocal
+            | Self::SetLocal
+            | Self::DecLocal
+            | Self::ZeroLocal
+            | Self::ClearLocal
+            | Self::PushList
+            | Self::ListAdd
+            | Self::ListDelete
+            | Self::ListDeleteAll
+            | Self::ListInsert
+            | Self::ListReplace
+            | Self::ListItem
+            | Self::ListLength
+            | Self::ListContains
+            | Self::ListIndexOf
+            | Self::PushConstant
+            | Self::PushUInt32
+            | Self::PeekStack
+            | Self::MathOp
+            | Self::DispatchEvent
+            | Self::CallBuiltin
+            | Self::CallProcedure
+            | Self::Jump
+            | Self::JumpIfTrue
+            | Self::JumpIfFalse => 1,
+            // The two halves of a little-endian f64
+            Self::PushNumber => 2,
+            Self::DoNothing
+            | Self::PushZero
+            | Self::Add
+            | Self::Subtract
+            | Self::Multiply
+            | Self::Divide
+            | Self::Modulo
+            | Self::Round
+            | Self::GreaterThan
+            | Self::LessThan
+            | Self::Equals
+            | Self::And
+            | Self::Or
+            | Self::Not
+            | Self::Return
+            | Self::Yield
+            | Self::Sleep => 0,
+        }
+    }
+}
+
+/// The function applied by [`Opcode::MathOp`], which is passed as its immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u32)]
+pub enum MathOp {
+    Abs,
+    Floor,
+    Ceiling,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Ln,
+    Log,
+    Exp,
+    Pow10,
+}
+
+impl MathOp {
+    /// Looks up an operator by its name in the `operator_mathop` dropdown.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Self::Abs,
+            "floor" => Self::Floor,
+            "ceiling" => Self::Ceiling,
+            "sqrt" => Self::Sqrt,
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "asin" => Self::Asin,
+            "acos" => Self::Acos,
+            "atan" => Self::Atan,
+            "ln" => Self::Ln,
+            "log" => Self::Log,
+            "e ^" => Self::Exp,
+            "10 ^" => Self::Pow10,
+            _ => return None,
+        })
+    }
+
+    /// Applies the operator with Scratch's semantics. Trigonometry is done in degrees, and
+    /// the results of sin, cos and tan are rounded to 10 decimal places so that e.g.
+    /// `sin 180` is exactly 0.
+    pub fn apply(self, num: f64) -> f64 {
+        fn round_10(num: f64) -> f64 {
+            (num * 1e10).round() / 1e10
+        }
+
+        match self {
+            Self::Abs => num.abs(),
+            Self::Floor => num.floor(),
+            Self::Ceiling => num.ceil(),
+            Self::Sqrt => num.sqrt(),
+            Self::Sin => round_10(num.to_radians().sin()),
+            Self::Cos => round_10(num.to_radians().cos()),
+            Self::Tan => match num % 360.0 {
+                -270.0 | 90.0 => f64::INFINITY,
+                -90.0 | 270.0 => f64::NEG_INFINITY,
+                angle => round_10(angle.to_radians().tan()),
+            },
+            Self::Asin => num.asin().to_degrees(),
+            Self::Acos => num.acos().to_degrees(),
+            Self::Atan => num.atan().to_degrees(),
+            Self::Ln => num.ln(),
+            // Scratch divides by ln 10 rather than using log10, so e.g. `log 1000` is
+            // 2.9999999999999996
+            Self::Log => num.ln() / LN_10,
+            Self::Exp => num.exp(),
+            Self::Pow10 => 10f64.powf(num),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u32)]
 pub enum BuiltinProcedure {
@@ -54,4 +211,42 @@ pub enum BuiltinProcedure {
 pub enum Trigger {
     OnStart,
     Event(Id<EventValue>),
+    /// The stage switched to the backdrop with the given name, in upper case.
+    BackdropSwitchedTo(Arc<str>),
+    /// A clone was created. Only the new clone's scripts are started.
+    CloneStarted,
+    /// A key was pressed, named like in Scratch's key menus but in upper case.
+    KeyPressed(Arc<str>),
+    /// A sprite was clicked. Only the scripts of the sprite or clone that was clicked are
+    /// started.
+    SpriteClicked,
+    StageClicked,
+    /// Fires on every frame. Scripts with this trigger start with a hat block that checks
+    /// its condition, and finish straight away unless the condition has just become true.
+    EdgeActivated,
+}
+
+impl Trigger {
+    /// Scratch matches backdrop names case-insensitively when starting hats.
+    pub fn backdrop_switched_to(name: &str) -> Self {
+        Self::BackdropSwitchedTo(name.to_uppercase().into())
+    }
+
+    /// Scratch matches key names case-insensitively when starting hats.
+    pub fn key_pressed(key: &str) -> Self {
+        Self::KeyPressed(key.to_uppercase().into())
+    }
+
+    /// Whether scripts that are still running when this trigger fires start over from the
+    /// top. Otherwise they're left to finish and aren't started again.
+    pub fn restarts_running(&self) -> bool {
+        match self {
+            Self::OnStart
+            | Self::Event(_)
+            | Self::BackdropSwitchedTo(_)
+            | Self::SpriteClicked
+            | Self::StageClicked => true,
+            Self::CloneStarted | Self::KeyPressed(_) | Self::EdgeActivated => false,
+        }
+    }
 }
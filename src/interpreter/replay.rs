@@ -0,0 +1,514 @@
+//! Recordings of the inputs a program was given, so that a run can be reproduced exactly.
+//!
+//! A recording holds the random seed, username, frame rate and start date of a run, along
+//! with every input the host injected and the frame it was injected before. Answers to
+//! questions are recorded with the frame they were given on. Recordings also keep a hash
+//! of the program's state after every frame and the final values of every variable, which
+//! a replay is checked against so that the first frame where it diverged can be reported.
+//!
+//! Replays always use a [`VirtualClock`], so a run is only reproducible if it was recorded
+//! with one too. The virtual clock also measures warp mode by counting loop iterations,
+//! so scripts that warp for longer than [`Program::warp_time`] still replay identically.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    hash::Hasher,
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
+
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::interpreter::{
+    Program,
+    clock::VirtualClock,
+    input::AnswerSource,
+    sprite::GraphicEffect,
+    value::{ListState, Value, VarState},
+};
+
+/// Everything needed to reproduce a run of a program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub username: Arc<str>,
+    pub frame_rate: u32,
+    /// The date the run started on, as a duration since the Unix epoch.
+    pub start_unix_time: Duration,
+    /// The number of frames the recording covers.
+    pub frame_count: u64,
+    /// The inputs in the order they were given.
+    pub inputs: Vec<RecordedInput>,
+    /// The hash of the program's state after each frame, starting with the first one.
+    /// Frames without a hash aren't checked during a replay, so hand-written recordings can
+    /// leave them out.
+    #[serde(default)]
+    pub frame_hashes: Vec<u64>,
+    /// The value of every variable when the recording finished, by target name and then
+    /// variable name. Values are stored as text.
+    #[serde(default)]
+    pub final_variables: BTreeMap<Arc<str>, BTreeMap<Arc<str>, Arc<str>>>,
+}
+
+impl Recording {
+    /// Starts a recording of the given program, which should be configured but not have
+    /// run any frames yet.
+    pub(super) fn start(program: &Program, seed: u64) -> Self {
+        Self {
+            seed,
+            username: program.username.clone(),
+            frame_rate: program.frame_rate,
+            start_unix_time: program.unix_time(),
+            frame_count: 0,
+            inputs: Vec::new(),
+            frame_hashes: Vec::new(),
+            final_variables: BTreeMap::new(),
+        }
+    }
+}
+
+/// An input given to a program, and the frame it was given on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    /// The first frame that saw the input. Inputs from the host are given between frames,
+    /// so this is the frame that ran after it.
+    pub frame: u64,
+    #[serde(flatten)]
+    pub input: HostInput,
+}
+
+/// Something the host application did to a program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostInput {
+    GreenFlag,
+    Stop,
+    KeyDown {
+        key: Arc<str>,
+    },
+    KeyUp {
+        key: Arc<str>,
+    },
+    MouseMove {
+        x: f64,
+        y: f64,
+    },
+    MouseDown {
+        x: f64,
+        y: f64,
+    },
+    MouseUp,
+    /// A target was clicked without using the mouse position.
+    Click {
+        target: usize,
+    },
+    /// The question being shown was answered.
+    Answer {
+        text: Arc<str>,
+    },
+}
+
+#[derive(Debug, Display, Error)]
+pub enum ReplayError {
+    #[display(
+        "frame {frame} diverged from the recording (expected {expected:016x}, got {actual:016x})"
+    )]
+    FrameMismatch {
+        frame: u64,
+        #[error(not(source))]
+        expected: u64,
+        #[error(not(source))]
+        actual: u64,
+    },
+    #[display("{target}'s variable {name:?} should be {expected:?}, but it's {actual:?}")]
+    VariableMismatch {
+        #[error(not(source))]
+        target: Arc<str>,
+        #[error(not(source))]
+        name: Arc<str>,
+        #[error(not(source))]
+        expected: Arc<str>,
+        #[error(not(source))]
+        actual: Arc<str>,
+    },
+    #[display("{target} has no variable {name:?}")]
+    MissingVariable {
+        #[error(not(source))]
+        target: Arc<str>,
+        #[error(not(source))]
+        name: Arc<str>,
+    },
+}
+
+/// Runs a freshly loaded program with the inputs from a recording, checking the state
+/// after every frame against the recording's hashes and the variables against its final
+/// values once it's done. Returns the number of frames that were run.
+pub fn replay(program: &mut Program, recording: &Recording) -> Result<u64, ReplayError> {
+    let answers = ReplayAnswers::default();
+
+    program.set_clock(VirtualClock::starting_at(recording.start_unix_time));
+    program.set_frame_rate(recording.frame_rate);
+    program.seed_rng(recording.seed);
+    program.set_username(recording.username.clone());
+    program.set_answer_source(answers.clone());
+
+    let mut inputs = recording.inputs.iter().peekable();
+
+    while program.frame_count() < recording.frame_count {
+        let frame = program.frame_count() + 1;
+
+        while let Some(recorded) = inputs.next_if(|recorded| recorded.frame <= frame) {
+            match &recorded.input {
+                HostInput::Answer { text } => answers.0.borrow_mut().push_back(text.clone()),
+                input => apply_input(program, input),
+            }
+        }
+
+        program.run_frame();
+
+        let Some(&expected) = recording.frame_hashes.get(frame as usize - 1) else {
+            continue;
+        };
+        let actual = state_hash(program);
+        if actual != expected {
+            return Err(ReplayError::FrameMismatch {
+                frame,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let actual = final_variables(program);
+    for (target, vars) in &recording.final_variables {
+        for (name, expected) in vars {
+            let Some(actual) = actual.get(target).and_then(|vars| vars.get(name)) else {
+                return Err(ReplayError::MissingVariable {
+                    target: target.clone(),
+                    name: name.clone(),
+                });
+            };
+
+            if actual != expected {
+                return Err(ReplayError::VariableMismatch {
+                    target: target.clone(),
+                    name: name.clone(),
+                    expected: expected.clone(),
+                    actual: actual.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(program.frame_count())
+}
+
+/// Gives an input to a program the way the host would have.
+fn apply_input(program: &mut Program, input: &HostInput) {
+    match input {
+        HostInput::GreenFlag => {
+            program.click_green_flag();
+        }
+        HostInput::Stop => program.click_stop(),
+        HostInput::KeyDown { key } => {
+            program.press_key(key);
+        }
+        HostInput::KeyUp { key } => program.release_key(key),
+        HostInput::MouseMove { x, y } => program.move_mouse(*x, *y),
+        HostInput::MouseDown { x, y } => {
+            program.mouse_down_at(*x, *y);
+        }
+        HostInput::MouseUp => program.mouse_up(),
+        HostInput::Click { target } => {
+            program.click_target(*target);
+        }
+        HostInput::Answer { .. } => unreachable!("answers are given through the answer source"),
+    }
+}
+
+/// Answers questions with the recorded answers once their frame has been reached.
+#[derive(Debug, Clone, Default)]
+struct ReplayAnswers(Rc<RefCell<VecDeque<Arc<str>>>>);
+
+impl AnswerSource for ReplayAnswers {
+    fn answer(&mut self, _question: &str) -> Option<Arc<str>> {
+        self.0.borrow_mut().pop_front()
+    }
+}
+
+/// The value of every variable in the program as text, by target name and then variable
+/// name. Global variables belong to the stage, and clones are left out since they share
+/// their names with the sprites they were cloned from.
+pub fn final_variables(program: &Program) -> BTreeMap<Arc<str>, BTreeMap<Arc<str>, Arc<str>>> {
+    let mut targets = BTreeMap::new();
+
+    for target in program.targets.iter().filter(|target| !target.is_clone()) {
+        let vars = if target.is_stage() {
+            &program.global_vars
+        } else {
+            &target.vars
+        };
+
+        let values = vars
+            .iter()
+            .map(|var| (var.name.clone(), program.dbg_string(&var.value.borrow())))
+            .collect();
+        targets.insert(target.name.clone(), values);
+    }
+
+    targets
+}
+
+/// Hashes the state of a program that scripts can observe: every variable and list, and
+/// the position, look and speech bubble of every target in layer order. Times and the
+/// state of tasks aren't included, so the same state always has the same hash.
+pub fn state_hash(program: &Program) -> u64 {
+    let mut hasher = StateHasher::default();
+
+    hash_data(&mut hasher, &program.global_vars, &program.global_lists);
+
+    for &target_id in &program.layers {
+        let target = &program.targets[target_id];
+        hasher.write_usize(target_id);
+        hash_data(&mut hasher, &target.vars, &target.lists);
+
+        hasher.write_usize(target.current_costume);
+        for effect in GraphicEffect::ALL {
+            hasher.write_u64(target.effects.get(effect).to_bits());
+        }
+
+        if let Some(sprite) = &target.sprite {
+            for num in [sprite.x, sprite.y, sprite.direction, sprite.size] {
+                hasher.write_u64(num.to_bits());
+            }
+            hasher.write_u8(sprite.visible as u8);
+            hasher.write_u8(sprite.draggable as u8);
+            hasher.write_u8(sprite.rotation_style as u8);
+
+            match &sprite.bubble {
+                Some(bubble) => {
+                    hasher.write_u8(bubble.kind as u8 + 1);
+                    hash_str(&mut hasher, &bubble.text);
+                }
+                None => hasher.write_u8(0),
+            }
+        }
+    }
+
+    hasher.finish()
+}
+
+fn hash_data(hasher: &mut StateHasher, vars: &[VarState], lists: &[ListState]) {
+    for var in vars {
+        hash_value(hasher, &var.value.borrow());
+    }
+
+    for list in lists {
+        let items = list.items.borrow();
+        hasher.write_usize(items.len());
+        for item in items.iter() {
+            hash_value(hasher, item);
+        }
+    }
+}
+
+fn hash_value(hasher: &mut StateHasher, value: &Value) {
+    match value {
+        Value::String(string) => {
+            hasher.write_u8(0);
+            hash_str(hasher, string);
+        }
+        Value::Number(num) => {
+            hasher.write_u8(1);
+            hasher.write_u64(num.to_bits());
+        }
+        Value::Boolean(bool) => {
+            hasher.write_u8(2);
+            hasher.write_u8(*bool as u8);
+        }
+        Value::ReturnLocation(location) => {
+            hasher.write_u8(3);
+            hasher.write_usize(*location);
+        }
+        Value::Event(id) => {
+            hasher.write_u8(4);
+            hasher.write_usize(id.get());
+        }
+        Value::Procedure(id) => {
+            hasher.write_u8(5);
+            hasher.write_usize(id.get());
+        }
+    }
+}
+
+fn hash_str(hasher: &mut StateHasher, string: &str) {
+    hasher.write_usize(string.len());
+    hasher.write(string.as_bytes());
+}
+
+/// A 64-bit FNV-1a hasher. Unlike the standard library's hasher, its output is stable
+/// across platforms and Rust versions, so hashes can be saved in recordings.
+#[derive(Debug)]
+struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    // Hashes shouldn't depend on the platform's byte order or pointer width
+
+    fn write_u64(&mut self, num: u64) {
+        self.write(&num.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, num: usize) {
+        self.write_u64(num as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostInput, Recording, ReplayError, replay};
+    use crate::{
+        ast::project::ScratchProject,
+        interpreter::{Program, clock::VirtualClock, input::ScriptedAnswers},
+        sb3::Sb3Project,
+    };
+
+    /// Asks for a name, then saves it joined with a random number.
+    const PROJECT: &str = r#"{"targets": [
+        {"isStage": true, "name": "Stage", "variables": {"v": ["result", 0]}, "lists": {},
+            "broadcasts": {}, "blocks": {}},
+        {"isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "broadcasts": {},
+            "blocks": {
+                "a0": {"opcode": "event_whenflagclicked", "next": "a1", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+                "a1": {"opcode": "sensing_askandwait", "next": "a2", "parent": "a0",
+                    "inputs": {"QUESTION": [1, [10, "name?"]]}, "fields": {}, "topLevel": false},
+                "a2": {"opcode": "data_setvariableto", "next": "a3", "parent": "a1",
+                    "inputs": {"VALUE": [3, "join", [10, ""]]},
+                    "fields": {"VARIABLE": ["result", "v"]}, "topLevel": false},
+                "a3": {"opcode": "looks_say", "next": null, "parent": "a2",
+                    "inputs": {"MESSAGE": [3, [12, "result", "v"], [10, ""]]}, "fields": {},
+                    "topLevel": false},
+                "join": {"opcode": "operator_join", "next": null, "parent": "a2",
+                    "inputs": {"STRING1": [3, "answer", [10, ""]], "STRING2": [3, "random", [10, ""]]},
+                    "fields": {}, "topLevel": false},
+                "answer": {"opcode": "sensing_answer", "next": null, "parent": "join",
+                    "inputs": {}, "fields": {}, "topLevel": false},
+                "random": {"opcode": "operator_random", "next": null, "parent": "join",
+                    "inputs": {"FROM": [1, [4, "1"]], "TO": [1, [4, "1000000"]]}, "fields": {},
+                    "topLevel": false}
+            }}
+    ]}"#;
+
+    /// Counts to 100,000 in a warp procedure, which runs for longer than the warp time
+    /// and so yields partway through.
+    const WARP_PROJECT: &str = r#"{"targets": [
+        {"isStage": true, "name": "Stage", "variables": {"v": ["n", 0]}, "lists": {},
+            "broadcasts": {}, "blocks": {}},
+        {"isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "broadcasts": {},
+            "blocks": {
+                "a0": {"opcode": "event_whenflagclicked", "next": "a1", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+                "a1": {"opcode": "procedures_call", "next": null, "parent": "a0", "inputs": {},
+                    "fields": {}, "topLevel": false,
+                    "mutation": {"tagName": "mutation", "children": [], "proccode": "count",
+                        "argumentids": "[]", "warp": "true"}},
+                "d0": {"opcode": "procedures_definition", "next": "d1", "parent": null,
+                    "inputs": {"custom_block": [1, "proto"]}, "fields": {}, "topLevel": true},
+                "proto": {"opcode": "procedures_prototype", "next": null, "parent": "d0",
+                    "inputs": {}, "fields": {}, "shadow": true, "topLevel": false,
+                    "mutation": {"tagName": "mutation", "children": [], "proccode": "count",
+                        "argumentids": "[]", "argumentnames": "[]", "argumentdefaults": "[]",
+                        "warp": "true"}},
+                "d1": {"opcode": "control_repeat", "next": null, "parent": "d0",
+                    "inputs": {"TIMES": [1, [6, "100000"]], "SUBSTACK": [2, "d2"]}, "fields": {},
+                    "topLevel": false},
+                "d2": {"opcode": "data_changevariableby", "next": null, "parent": "d1",
+                    "inputs": {"VALUE": [1, [4, "1"]]}, "fields": {"VARIABLE": ["n", "v"]},
+                    "topLevel": false}
+            }}
+    ]}"#;
+
+    fn compile(json: &str) -> Program {
+        let sb3: Sb3Project = serde_json::from_str(json).unwrap();
+        ScratchProject::try_from(sb3).unwrap().compile()
+    }
+
+    fn record(json: &str, answer: &str, seed: u64) -> Recording {
+        let mut program = compile(json);
+        program.set_clock(VirtualClock::new());
+        program.set_answer_source(ScriptedAnswers::new([answer]));
+        program.start_recording(seed);
+
+        program.click_green_flag();
+        while program.has_incomplete_tasks() {
+            program.run_frame();
+        }
+
+        program.finish_recording().unwrap()
+    }
+
+    #[test]
+    fn replays_recording() {
+        let recording = record(PROJECT, "Ada", 1);
+        assert!(recording.final_variables["Stage"]["result"].starts_with("Ada"));
+
+        // Recordings are saved as JSON, so replay one that went through it
+        let json = serde_json::to_string(&recording).unwrap();
+        let recording: Recording = serde_json::from_str(&json).unwrap();
+
+        let frames = replay(&mut compile(PROJECT), &recording).unwrap();
+        assert_eq!(frames, recording.frame_count);
+    }
+
+    #[test]
+    fn detects_changed_seed() {
+        let mut recording = record(PROJECT, "Ada", 1);
+        recording.seed = 2;
+
+        let result = replay(&mut compile(PROJECT), &recording);
+        assert!(matches!(result, Err(ReplayError::FrameMismatch { .. })), "{result:?}");
+    }
+
+    #[test]
+    fn detects_changed_answer() {
+        let mut recording = record(PROJECT, "Ada", 1);
+        let answer = recording.inputs.iter_mut().find_map(|recorded| match &mut recorded.input {
+            HostInput::Answer { text } => Some(text),
+            _ => None,
+        });
+        *answer.expect("answer should be recorded") = "Grace".into();
+
+        let result = replay(&mut compile(PROJECT), &recording);
+        assert!(matches!(result, Err(ReplayError::FrameMismatch { .. })), "{result:?}");
+    }
+
+    #[test]
+    fn replays_long_warp() {
+        let recording = record(WARP_PROJECT, "", 1);
+        assert_eq!(&*recording.final_variables["Stage"]["n"], "100000");
+        assert!(recording.frame_count > 1, "warp should yield for a redraw");
+
+        let frames = replay(&mut compile(WARP_PROJECT), &recording).unwrap();
+        assert_eq!(frames, recording.frame_count);
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small, seedable xorshift64* generator backing Scratch's random number blocks.
+///
+/// Scratch doesn't make any guarantees about the quality of its random numbers, so this
+/// favors being fast and reproducible from a seed over being statistically robust.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeroes.
+        Self {
+            state: seed.max(1),
+        }
+    }
+
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in the range `[0, 1)`, like JavaScript's `Math.random()`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,50 @@
+use std::{ops::Range, sync::Arc};
+
+/// The block in a project that some bytecode was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSource {
+    /// The id of the block in the sb3 project.
+    pub id: Arc<str>,
+    pub opcode: Arc<str>,
+}
+
+/// A range of bytecode and the block it was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub range: Range<usize>,
+    pub block: BlockSource,
+}
+
+/// Maps locations in a procedure's bytecode back to the blocks they were compiled from.
+/// Blocks nest, so a location can be covered by several spans, in which case the
+/// innermost block is the one that's reported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    spans: Vec<SourceSpan>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that a range of bytecode came from a block. Empty ranges are ignored.
+    pub fn insert(&mut self, range: Range<usize>, block: BlockSource) {
+        if !range.is_empty() {
+            self.spans.push(SourceSpan { range, block });
+        }
+    }
+
+    pub fn spans(&self) -> &[SourceSpan] {
+        &self.spans
+    }
+
+    /// Finds the innermost block that the instruction at a location was compiled from.
+    pub fn block_at(&self, location: usize) -> Option<&BlockSource> {
+        self.spans
+            .iter()
+            .filter(|span| span.range.contains(&location))
+            .min_by_key(|span| span.range.len())
+            .map(|span| &span.block)
+    }
+}
@@ -0,0 +1,278 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    ast::{Costume, RotationStyle, Sprite},
+    interpreter::{Task, id::Id},
+};
+
+pub const STAGE_WIDTH: f64 = 480.0;
+pub const STAGE_HEIGHT: f64 = 360.0;
+
+/// How much of a sprite must stay on the stage when it's moved near an edge.
+const FENCE_WIDTH: f64 = 15.0;
+
+/// The runtime state of a sprite, which motion and looks blocks operate on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteState {
+    pub x: f64,
+    pub y: f64,
+    pub direction: f64,
+    pub size: f64,
+    pub visible: bool,
+    pub draggable: bool,
+    pub rotation_style: RotationStyle,
+    pub bubble: Option<Bubble>,
+}
+
+impl SpriteState {
+    /// Points the sprite in a direction, wrapping it into Scratch's (-180, 180] range.
+    pub fn set_direction(&mut self, direction: f64) {
+        if direction.is_finite() {
+            self.direction = wrap_clamp(direction, -179.0, 180.0);
+        }
+    }
+
+    /// Scales the sprite to a percentage of its costume's size, clamped so that it can't
+    /// get too small to see or much bigger than the stage.
+    pub fn set_size(&mut self, costume: Option<&Costume>, size: f64) {
+        let (width, height) = costume.map_or((0.0, 0.0), costume_size);
+        if width <= 0.0 || height <= 0.0 {
+            self.size = size;
+            return;
+        }
+
+        let min_scale = (5.0 / width).max(5.0 / height).min(1.0);
+        let max_scale = (1.5 * STAGE_WIDTH / width).min(1.5 * STAGE_HEIGHT / height);
+        self.size = (size / 100.0).clamp(min_scale, max_scale) * 100.0;
+    }
+
+    /// Estimates the axis-aligned bounds of the sprite on the stage.
+    pub fn bounds(&self, costume: Option<&Costume>) -> Bounds {
+        let (width, height) = costume.map_or((0.0, 0.0), costume_size);
+        let scale = self.size / 100.0;
+        let (half_width, half_height) = (width * scale / 2.0, height * scale / 2.0);
+
+        let (half_width, half_height) = match self.rotation_style {
+            RotationStyle::AllAround => {
+                let (sin, cos) = (self.direction - 90.0).to_radians().sin_cos();
+                (
+                    (half_width * cos).abs() + (half_height * sin).abs(),
+                    (half_width * sin).abs() + (half_height * cos).abs(),
+                )
+            }
+            // Flipping a centered costume doesn't change its bounds.
+            RotationStyle::LeftRight | RotationStyle::DontRotate => (half_width, half_height),
+        };
+
+        Bounds {
+            left: self.x - half_width,
+            right: self.x + half_width,
+            top: self.y + half_height,
+            bottom: self.y - half_height,
+        }
+    }
+
+    /// Moves a position the sprite is about to go to so that part of it stays visible
+    /// on the stage, like Scratch's `keepInFence`.
+    pub fn fenced_position(&self, costume: Option<&Costume>, x: f64, y: f64) -> (f64, f64) {
+        let bounds = self.bounds(costume);
+        let inset = (bounds.width().min(bounds.height()) / 2.0).floor();
+        let (dx, dy) = (x - self.x, y - self.y);
+
+        let sx = STAGE_WIDTH / 2.0 - FENCE_WIDTH.min(inset);
+        let x = if bounds.right + dx < -sx {
+            (self.x - (sx + bounds.right)).ceil()
+        } else if bounds.left + dx > sx {
+            (self.x + (sx - bounds.left)).floor()
+        } else {
+            x
+        };
+
+        let sy = STAGE_HEIGHT / 2.0 - FENCE_WIDTH.min(inset);
+        let y = if bounds.top + dy < -sy {
+            (self.y - (sy + bounds.top)).ceil()
+        } else if bounds.bottom + dy > sy {
+            (self.y + (sy - bounds.bottom)).floor()
+        } else {
+            y
+        };
+
+        (x, y)
+    }
+
+    /// Moves a position the sprite is about to go to so that all of it is on the stage,
+    /// like Scratch's `keepInFence`.
+    pub fn position_on_stage(&self, costume: Option<&Costume>, x: f64, y: f64) -> (f64, f64) {
+        let bounds = self.bounds(costume);
+        let (dx, dy) = (x - self.x, y - self.y);
+        let (left, right) = (bounds.left + dx, bounds.right + dx);
+        let (top, bottom) = (bounds.top + dy, bounds.bottom + dy);
+
+        let mut x = x;
+        if left < -STAGE_WIDTH / 2.0 {
+            x += -STAGE_WIDTH / 2.0 - left;
+        }
+        if right > STAGE_WIDTH / 2.0 {
+            x += STAGE_WIDTH / 2.0 - right;
+        }
+
+        let mut y = y;
+        if top > STAGE_HEIGHT / 2.0 {
+            y += STAGE_HEIGHT / 2.0 - top;
+        }
+        if bottom < -STAGE_HEIGHT / 2.0 {
+            y += -STAGE_HEIGHT / 2.0 - bottom;
+        }
+
+        (x, y)
+    }
+}
+
+impl From<&Sprite> for SpriteState {
+    fn from(value: &Sprite) -> Self {
+        Self {
+            x: value.x,
+            y: value.y,
+            direction: value.direction,
+            size: value.size,
+            visible: value.visible,
+            draggable: value.draggable,
+            rotation_style: value.rotation_style,
+            bubble: None,
+        }
+    }
+}
+
+/// A speech or thought bubble shown next to a sprite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bubble {
+    pub kind: BubbleKind,
+    pub text: Arc<str>,
+    /// The task that ran the block which showed this bubble.
+    pub owner: Id<Task>,
+    /// When the bubble will be cleared, if it was shown for a number of seconds.
+    pub expires_at: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BubbleKind {
+    Say,
+    Think,
+}
+
+/// Estimates the size of a costume at 100% scale, in stage pixels.
+///
+/// Costume images aren't decoded, so the costume is assumed to be centered on its
+/// rotation center.
+pub fn costume_size(costume: &Costume) -> (f64, f64) {
+    let (center_x, center_y) = costume.rotation_center;
+    let scale = 2.0 / costume.bitmap_resolution;
+    (center_x.abs() * scale, center_y.abs() * scale)
+}
+
+/// A graphic effect that can be applied to a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicEffect {
+    Color,
+    Fisheye,
+    Whirl,
+    Pixelate,
+    Mosaic,
+    Brightness,
+    Ghost,
+}
+
+impl GraphicEffect {
+    pub const ALL: [Self; 7] = [
+        Self::Color,
+        Self::Fisheye,
+        Self::Whirl,
+        Self::Pixelate,
+        Self::Mosaic,
+        Self::Brightness,
+        Self::Ghost,
+    ];
+
+    /// Parses the value of an `EFFECT` field, which Scratch matches case-insensitively.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|effect| effect.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Color => "COLOR",
+            Self::Fisheye => "FISHEYE",
+            Self::Whirl => "WHIRL",
+            Self::Pixelate => "PIXELATE",
+            Self::Mosaic => "MOSAIC",
+            Self::Brightness => "BRIGHTNESS",
+            Self::Ghost => "GHOST",
+        }
+    }
+
+    /// Limits a value to the range the effect accepts. Only ghost and brightness have
+    /// limits, the other effects wrap around or grow indefinitely.
+    pub fn clamp(self, value: f64) -> f64 {
+        match self {
+            Self::Ghost => value.clamp(0.0, 100.0),
+            Self::Brightness => value.clamp(-100.0, 100.0),
+            _ => value,
+        }
+    }
+}
+
+/// The values of every graphic effect on a target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphicEffects([f64; GraphicEffect::ALL.len()]);
+
+impl GraphicEffects {
+    pub fn get(&self, effect: GraphicEffect) -> f64 {
+        self.0[effect as usize]
+    }
+
+    pub fn set(&mut self, effect: GraphicEffect, value: f64) {
+        self.0[effect as usize] = effect.clamp(value);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// An axis-aligned rectangle in stage coordinates, where up is positive y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub left: f64,
+    pub right: f64,
+    pub top: f64,
+    pub bottom: f64,
+}
+
+impl Bounds {
+    pub fn width(&self) -> f64 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f64 {
+        self.top - self.bottom
+    }
+}
+
+/// Wraps a number into the inclusive range `min..=max`, treating it as a cycle.
+pub fn wrap_clamp(value: f64, min: f64, max: f64) -> f64 {
+    let range = max - min + 1.0;
+    value - ((value - min) / range).floor() * range
+}
+
+/// Rounds coordinates that are within floating point error of an integer, so that
+/// reporters don't show values like `9.999999999999998`.
+pub fn limit_precision(coordinate: f64) -> f64 {
+    let rounded = coordinate.round();
+    if (coordinate - rounded).abs() < 1e-9 {
+        rounded
+    } else {
+        coordinate
+    }
+}
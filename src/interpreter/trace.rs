@@ -0,0 +1,146 @@
+use std::{
+    fmt::{self, Debug, Display},
+    io::{self, Write},
+};
+
+use crate::interpreter::{
+    Task,
+    id::Id,
+    opcode::Opcode,
+    source_map::BlockSource,
+    value::{EventValue, Value},
+};
+
+/// How much detail the interpreter records while it runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    /// Nothing is recorded.
+    #[default]
+    Off,
+    /// Broadcasts sent by scripts are recorded.
+    Broadcasts,
+    /// Every opcode that runs is recorded, along with broadcasts.
+    Opcodes,
+}
+
+impl TraceLevel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "off" => Self::Off,
+            "broadcasts" => Self::Broadcasts,
+            "opcodes" => Self::Opcodes,
+            _ => return None,
+        })
+    }
+}
+
+/// Something that happened while a task was running.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceRecord<'a> {
+    pub frame: u64,
+    pub task: Id<Task>,
+    pub procedure: &'a str,
+    /// The location of the instruction in the procedure's bytecode.
+    pub pc: usize,
+    /// The block the instruction was compiled from, if it came from one.
+    pub block: Option<&'a BlockSource>,
+    pub event: TraceEvent<'a>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEvent<'a> {
+    /// An opcode is about to run with the given stack.
+    Opcode { opcode: Opcode, stack: &'a [Value] },
+    /// A broadcast is about to be sent.
+    Broadcast { event: Id<EventValue>, name: &'a str },
+}
+
+/// The destination of trace records.
+pub trait TraceSink: Debug {
+    fn record(&mut self, record: &TraceRecord);
+}
+
+/// Writes trace records as text, with one line per record. A header is written whenever
+/// the running task or procedure changes. Records don't include timings, so traces from
+/// different runs can be diffed.
+#[derive(Debug)]
+pub struct TextTraceSink<W> {
+    writer: W,
+    /// The frame, task and procedure of the last header that was written.
+    current: Option<(u64, Id<Task>, String)>,
+}
+
+impl<W: Write> TextTraceSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            current: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let is_current = self.current.as_ref().is_some_and(|(frame, task, procedure)| {
+            (*frame, *task, procedure.as_str()) == (record.frame, record.task, record.procedure)
+        });
+
+        if !is_current {
+            self.current = Some((record.frame, record.task, record.procedure.to_owned()));
+
+            let title = format!(
+                " Frame {} -- Task {} -- {} ",
+                record.frame,
+                record.task.get(),
+                record.procedure
+            );
+            writeln!(self.writer, "{title:-^80}")?;
+        }
+
+        match record.event {
+            TraceEvent::Opcode { opcode, stack } => {
+                let opcode = format!("{opcode:?}");
+                write!(self.writer, "{:06}    {opcode:<16} {}", record.pc, StackDisplay(stack))?;
+            }
+            TraceEvent::Broadcast { event, name } => {
+                write!(self.writer, "{:06}    > broadcast {name:?} ({})", record.pc, event.get())?;
+            }
+        }
+
+        match record.block {
+            Some(block) => writeln!(self.writer, "    @ {} {:?}", block.opcode, block.id),
+            None => writeln!(self.writer),
+        }
+    }
+}
+
+impl<W: Write + Debug> TraceSink for TextTraceSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        // Tracing is best-effort, so a broken pipe shouldn't bring down the program
+        let _ = self.write_record(record);
+    }
+}
+
+/// Formats a stack compactly, such as `[1, "abc", true]`.
+struct StackDisplay<'a>(&'a [Value]);
+
+impl Display for StackDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (idx, value) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+
+            match value {
+                Value::String(string) => write!(f, "{string:?}")?,
+                Value::Number(num) => write!(f, "{num}")?,
+                Value::Boolean(bool) => write!(f, "{bool}")?,
+                other => write!(f, "{other:?}")?,
+            }
+        }
+        f.write_str("]")
+    }
+}
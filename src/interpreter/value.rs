@@ -1,12 +1,16 @@
 use std::{
     borrow::Cow,
     cell::{OnceCell, RefCell},
+    cmp::Ordering,
     rc::Rc, sync::Arc,
 };
 
 use derive_more::{AsRef, From, Unwrap};
 
-use crate::{ast::Variable, interpreter::id::Id};
+use crate::{
+    ast::{List, Variable},
+    interpreter::{id::Id, rng::Rng, source_map::SourceMap},
+};
 
 #[derive(Debug, Clone, Unwrap, From, PartialEq)]
 pub enum Value {
@@ -22,7 +26,7 @@ impl Value {
     pub fn cast_string(&self) -> Arc<str> {
         match self {
             Value::String(string) => string.clone(),
-            &Value::Number(num) => num.to_string().into(),
+            &Value::Number(num) => format_number(num).into(),
             &Value::Boolean(bool) => if bool { "true" } else { "false" }.into(),
             val => unimplemented!("cast {val:?} => string"),
         }
@@ -30,23 +34,266 @@ impl Value {
 
     pub fn cast_number(&self) -> f64 {
         match self {
+            &Value::Number(num) if num.is_nan() => 0.0,
             &Value::Number(num) => num,
-            Value::String(string) => string.parse().unwrap_or(0.0),
+            Value::String(string) => {
+                let num = parse_number(string);
+                if num.is_nan() { 0.0 } else { num }
+            }
             &Value::Boolean(bool) => bool.into(),
             val => unimplemented!("cast {val:?} => number"),
         }
     }
 
+    /// Whether this value should be treated as an integer by blocks like `pick random`,
+    /// which Scratch decides based on how the value was written rather than its magnitude.
+    pub fn is_integer_like(&self) -> bool {
+        match self {
+            &Value::Number(num) => num.is_nan() || num.fract() == 0.0,
+            Value::String(string) => !string.contains('.'),
+            Value::Boolean(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Compares two values the way Scratch does: numerically if both values look like
+    /// numbers, otherwise as case-insensitive strings.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        let mut n1 = self.loose_number();
+        let mut n2 = other.loose_number();
+
+        if n1 == 0.0 && self.is_whitespace() {
+            n1 = f64::NAN;
+        } else if n2 == 0.0 && other.is_whitespace() {
+            n2 = f64::NAN;
+        }
+
+        if n1.is_nan() || n2.is_nan() {
+            // JavaScript compares strings by their UTF-16 code units
+            let s1 = self.cast_string().to_lowercase();
+            let s2 = other.cast_string().to_lowercase();
+            return s1.encode_utf16().cmp(s2.encode_utf16());
+        }
+
+        n1.partial_cmp(&n2).unwrap_or(Ordering::Equal)
+    }
+
+    /// Converts this value to a number like JavaScript's `Number()`, which unlike
+    /// [`Value::cast_number`] produces NaN for text that isn't numeric.
+    fn loose_number(&self) -> f64 {
+        match self {
+            &Value::Number(num) => num,
+            Value::String(string) => parse_number(string),
+            &Value::Boolean(bool) => bool.into(),
+            _ => f64::NAN,
+        }
+    }
+
+    fn is_whitespace(&self) -> bool {
+        matches!(self, Value::String(string) if string.trim_matches(is_js_whitespace).is_empty())
+    }
+
+    /// The number this value represents, or `None` if it's text that isn't numeric or is
+    /// only whitespace.
+    pub fn try_as_number(&self) -> Option<f64> {
+        let num = self.loose_number();
+        (!num.is_nan() && !self.is_whitespace()).then_some(num)
+    }
+
+    /// Resolves this value to a 1-based index into a list of the given length, following
+    /// Scratch's special cases for `"all"`, `"last"` and `"random"`.
+    pub fn cast_list_index(&self, length: usize, accept_all: bool, rng: &mut Rng) -> ListIndex {
+        if let Value::String(string) = self {
+            match &**string {
+                "all" if accept_all => return ListIndex::All,
+                "all" => return ListIndex::Invalid,
+                "last" if length > 0 => return ListIndex::Item(length),
+                "last" => return ListIndex::Invalid,
+                "random" | "any" if length > 0 => {
+                    let idx = (rng.next_f64() * length as f64) as usize;
+                    return ListIndex::Item(idx + 1);
+                }
+                "random" | "any" => return ListIndex::Invalid,
+                _ => {}
+            }
+        }
+
+        let index = self.cast_number().floor();
+        if index < 1.0 || index > length as f64 {
+            ListIndex::Invalid
+        } else {
+            ListIndex::Item(index as usize)
+        }
+    }
+
+    /// Converts this value to a boolean. Like in Scratch, the strings `"0"` and `"false"`
+    /// (in any case) are false along with the empty string.
     pub fn cast_boolean(&self) -> bool {
         match self {
             &Value::Boolean(bool) => bool,
-            Value::String(string) => !string.is_empty(),
-            &Value::Number(num) => num != 0.0,
+            Value::String(string) => {
+                !(string.is_empty() || &**string == "0" || string.to_lowercase() == "false")
+            }
+            &Value::Number(num) => num != 0.0 && !num.is_nan(),
             val => unimplemented!("cast {val:?} => boolean"),
         }
     }
 }
 
+/// Parses text like JavaScript's `Number()`, which Scratch uses to convert text to numbers.
+/// Surrounding whitespace is ignored and empty text is zero. Besides decimal numbers,
+/// `Infinity` and unsigned hexadecimal, octal and binary literals such as `0x10` are
+/// accepted. Anything else is NaN.
+pub fn parse_number(text: &str) -> f64 {
+    let text = text.trim_matches(is_js_whitespace);
+    if text.is_empty() {
+        return 0.0;
+    }
+
+    let prefix = text.get(..2).map(str::to_ascii_lowercase);
+    let radix_bits = match prefix.as_deref() {
+        Some("0x") => Some(4),
+        Some("0o") => Some(3),
+        Some("0b") => Some(1),
+        _ => None,
+    };
+    if let Some(bits) = radix_bits {
+        return parse_radix(&text[2..], bits);
+    }
+
+    let (sign, unsigned) = match text.as_bytes()[0] {
+        b'-' => (-1.0, &text[1..]),
+        b'+' => (1.0, &text[1..]),
+        _ => (1.0, text),
+    };
+
+    if unsigned == "Infinity" {
+        sign * f64::INFINITY
+    } else if is_decimal_literal(unsigned) {
+        // Rust accepts a superset of JavaScript's decimal literals and rounds them the same
+        sign * unsigned.parse::<f64>().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+/// Parses the digits of a hexadecimal, octal or binary literal, rounding to the nearest
+/// number like JavaScript does.
+fn parse_radix(digits: &str, bits: u32) -> f64 {
+    if digits.is_empty() {
+        return f64::NAN;
+    }
+
+    let mut mantissa = 0u64;
+    let mut shift = 0;
+    let mut sticky = false;
+
+    for char in digits.chars() {
+        let Some(digit) = char.to_digit(1 << bits) else {
+            return f64::NAN;
+        };
+
+        if mantissa >> (64 - bits) == 0 {
+            mantissa = mantissa << bits | u64::from(digit);
+        } else {
+            // The dropped digits only matter for rounding, which the lowest bit can stand in
+            // for since it's well below the bits a float keeps
+            shift += bits;
+            sticky |= digit != 0;
+        }
+    }
+
+    (mantissa | u64::from(sticky)) as f64 * 2f64.powi(shift as i32)
+}
+
+/// Whether text is an unsigned decimal literal, such as `12`, `1.5`, `.5`, `5.` or `1e-3`.
+fn is_decimal_literal(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let digits_from = |start: usize| {
+        bytes[start.min(bytes.len())..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+
+    let int_digits = digits_from(0);
+    let mut idx = int_digits;
+    let mut frac_digits = 0;
+    if bytes.get(idx) == Some(&b'.') {
+        frac_digits = digits_from(idx + 1);
+        idx += 1 + frac_digits;
+    }
+    if int_digits == 0 && frac_digits == 0 {
+        return false;
+    }
+
+    if matches!(bytes.get(idx), Some(b'e' | b'E')) {
+        idx += 1;
+        if matches!(bytes.get(idx), Some(b'+' | b'-')) {
+            idx += 1;
+        }
+        let exp_digits = digits_from(idx);
+        if exp_digits == 0 {
+            return false;
+        }
+        idx += exp_digits;
+    }
+
+    idx == bytes.len()
+}
+
+/// Whether a character is whitespace or a line terminator to JavaScript, which differs
+/// slightly from Rust's idea of whitespace.
+fn is_js_whitespace(char: char) -> bool {
+    let is_line_terminator = matches!(char, '\n' | '\r' | '\u{2028}' | '\u{2029}');
+    let is_space_separator = matches!(char, ' ' | '\u{a0}' | '\u{1680}' | '\u{2000}'..='\u{200a}')
+        || matches!(char, '\u{202f}' | '\u{205f}' | '\u{3000}');
+
+    let is_other_space = matches!(char, '\t' | '\u{b}' | '\u{c}' | '\u{feff}');
+
+    is_line_terminator || is_space_separator || is_other_space
+}
+
+/// Formats a number like JavaScript's `String()`, which Scratch uses to display numbers.
+/// Numbers are written with the fewest digits that read back as the same number, using
+/// exponent notation below 1e-6 and from 1e21. Negative zero is written as `0`.
+pub fn format_number(num: f64) -> String {
+    if num.is_nan() {
+        return "NaN".to_owned();
+    } else if num == 0.0 {
+        return "0".to_owned();
+    } else if num.is_infinite() {
+        return if num > 0.0 { "Infinity" } else { "-Infinity" }.to_owned();
+    }
+
+    // Rust's shortest representation has the same digits that JavaScript picks
+    let scientific = format!("{:e}", num.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let digit_count = digits.len() as i32;
+    // The position of the decimal point relative to the start of the digits
+    let point = exponent.parse::<i32>().unwrap() + 1;
+
+    let sign = if num < 0.0 { "-" } else { "" };
+    if digit_count <= point && point <= 21 {
+        let zeros = "0".repeat((point - digit_count) as usize);
+        format!("{sign}{digits}{zeros}")
+    } else if 0 < point && point <= 21 {
+        let (int, frac) = digits.split_at(point as usize);
+        format!("{sign}{int}.{frac}")
+    } else if -6 < point && point <= 0 {
+        let zeros = "0".repeat(-point as usize);
+        format!("{sign}0.{zeros}{digits}")
+    } else {
+        let exponent = point - 1;
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exponent = exponent.abs();
+        format!("{sign}{first}{point}{rest}e{exponent_sign}{exponent}")
+    }
+}
+
 impl From<String> for Value {
     fn from(value: String) -> Self {
         Self::String(value.into())
@@ -67,13 +314,14 @@ impl Default for Value {
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct ProcedureValue {
-    name: Option<Arc<str>>,
+    pub(super) name: Option<Arc<str>>,
     pub(crate) param_count: usize,
     pub(crate) locals: Box<[Local]>,
     bytecode: Box<[u32]>,
     pub(super) ident: OnceCell<Id<Self>>,
     pub(super) target_id: usize,
     pub(super) warp: bool,
+    source_map: SourceMap,
 }
 
 impl ProcedureValue {
@@ -97,9 +345,20 @@ impl ProcedureValue {
             ident: OnceCell::new(),
             target_id,
             warp,
+            source_map: SourceMap::new(),
         }
     }
 
+    /// Attaches a map from the procedure's bytecode back to the blocks it came from.
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Self {
+        self.source_map = source_map;
+        self
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_deref().unwrap_or("{unnamed}")
     }
@@ -153,6 +412,53 @@ impl AsRef<RefCell<Value>> for VarState {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListIndex {
+    /// A 1-based index of an item in the list.
+    Item(usize),
+    All,
+    Invalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListState {
+    pub name: Arc<str>,
+    pub items: RefCell<Vec<Value>>,
+}
+
+impl ListState {
+    /// The maximum number of items Scratch allows in a single list.
+    pub const MAX_ITEMS: usize = 200_000;
+
+    pub fn new(list: List) -> Self {
+        Self {
+            name: list.reference.name(),
+            items: list.initial_items.into(),
+        }
+    }
+
+    /// Renders the list the way Scratch's list reporter does: items are separated by
+    /// spaces unless every item is a single character, in which case they're joined directly.
+    pub fn contents(&self) -> Arc<str> {
+        let items = self.items.borrow();
+        let strings = items.iter().map(Value::cast_string).collect::<Vec<_>>();
+
+        let separator = if strings.iter().all(|s| s.chars().count() == 1) {
+            ""
+        } else {
+            " "
+        };
+
+        strings.join(separator).into()
+    }
+}
+
+impl AsRef<RefCell<Vec<Value>>> for ListState {
+    fn as_ref(&self) -> &RefCell<Vec<Value>> {
+        &self.items
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Local {
     name: Option<Arc<str>>,
@@ -162,6 +468,10 @@ impl Local {
     pub fn new(name: Option<Arc<str>>) -> Self {
         Self { name }
     }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
 impl From<&str> for Local {
@@ -171,3 +481,161 @@ impl From<&str> for Local {
         }
     }
 }
+
+/// Conformance tables for Scratch's casts, with the results given by scratch-vm's `Cast`.
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{Value, format_number};
+
+    fn text(text: &str) -> Value {
+        Value::from(text)
+    }
+
+    #[test]
+    fn to_number() {
+        let cases = [
+            (text(""), 0.0),
+            (text("   "), 0.0),
+            (text(" 5 "), 5.0),
+            (text("\u{a0}7\n"), 7.0),
+            (text("\u{feff}3"), 3.0),
+            (text("\u{85}3"), 0.0),
+            (text("12"), 12.0),
+            (text("00012"), 12.0),
+            (text("+5"), 5.0),
+            (text("-5"), -5.0),
+            (text("1.5"), 1.5),
+            (text(".5"), 0.5),
+            (text("5."), 5.0),
+            (text("-.5e1"), -5.0),
+            (text("1e3"), 1000.0),
+            (text("1E+3"), 1000.0),
+            (text("1e-3"), 0.001),
+            (text("1e1000"), f64::INFINITY),
+            (text("0x10"), 16.0),
+            (text("0X1f"), 31.0),
+            (text("0o17"), 15.0),
+            (text("0b101"), 5.0),
+            (text("0x1fffffffffffff"), 9007199254740991.0),
+            (text("0x20000000000001"), 9007199254740992.0),
+            (text("0x20000000000003"), 9007199254740996.0),
+            (text("0x2000000000000100000001"), 3.868562622766814e25),
+            (text("0x1000000000000000000000001"), 79228162514264337593543950336.0),
+            (text("Infinity"), f64::INFINITY),
+            (text("+Infinity"), f64::INFINITY),
+            (text("-Infinity"), f64::NEG_INFINITY),
+            (text("-0x10"), 0.0),
+            (text("0x"), 0.0),
+            (text("0b2"), 0.0),
+            (text("infinity"), 0.0),
+            (text("inf"), 0.0),
+            (text("NaN"), 0.0),
+            (text("1_000"), 0.0),
+            (text("12abc"), 0.0),
+            (text("1e"), 0.0),
+            (text("e5"), 0.0),
+            (text("."), 0.0),
+            (text("- 5"), 0.0),
+            (Value::Boolean(true), 1.0),
+            (Value::Boolean(false), 0.0),
+            (Value::Number(f64::NAN), 0.0),
+            (Value::Number(-2.5), -2.5),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(value.cast_number(), expected, "casting {value:?}");
+        }
+    }
+
+    #[test]
+    fn to_string() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+            (100.0, "100"),
+            (-1.5, "-1.5"),
+            (123.456, "123.456"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (9007199254740992.0, "9007199254740992"),
+            (1e20, "100000000000000000000"),
+            (123456789012345680000.0, "123456789012345680000"),
+            (1e21, "1e+21"),
+            (1.5e21, "1.5e+21"),
+            (0.000001, "0.000001"),
+            (0.0000015, "0.0000015"),
+            (1e-7, "1e-7"),
+            (-1.5e-7, "-1.5e-7"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (5e-324, "5e-324"),
+        ];
+
+        for (num, expected) in cases {
+            assert_eq!(format_number(num), expected, "formatting {num:?}");
+            assert_eq!(&*Value::Number(num).cast_string(), expected);
+        }
+
+        assert_eq!(&*Value::Boolean(true).cast_string(), "true");
+        assert_eq!(&*text("0x10").cast_string(), "0x10");
+    }
+
+    #[test]
+    fn to_boolean() {
+        let cases = [
+            (text(""), false),
+            (text("0"), false),
+            (text("false"), false),
+            (text("FALSE"), false),
+            (text("False"), false),
+            (text(" false"), true),
+            (text("0.0"), true),
+            (text(" "), true),
+            (text("no"), true),
+            (text("true"), true),
+            (Value::Number(0.0), false),
+            (Value::Number(-0.0), false),
+            (Value::Number(f64::NAN), false),
+            (Value::Number(1.0), true),
+            (Value::Number(-0.5), true),
+            (Value::Boolean(true), true),
+            (Value::Boolean(false), false),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(value.cast_boolean(), expected, "casting {value:?}");
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let cases = [
+            (text("10"), text("9"), Ordering::Greater),
+            (text("abc"), text("ABC"), Ordering::Equal),
+            (text("a"), text("b"), Ordering::Less),
+            (text(""), Value::Number(0.0), Ordering::Less),
+            (text(" "), text(" "), Ordering::Equal),
+            (text("1e3"), Value::Number(1000.0), Ordering::Equal),
+            (text("0x10"), Value::Number(16.0), Ordering::Equal),
+            (text(" 5 "), Value::Number(5.0), Ordering::Equal),
+            (text("Infinity"), Value::Number(f64::INFINITY), Ordering::Equal),
+            (text("-Infinity"), Value::Number(f64::NEG_INFINITY), Ordering::Equal),
+            (Value::Boolean(true), text("true"), Ordering::Equal),
+            (Value::Boolean(true), Value::Number(1.0), Ordering::Equal),
+            (text("apple"), Value::Number(5.0), Ordering::Greater),
+            (Value::Number(f64::NAN), text("nan"), Ordering::Equal),
+            // Strings are compared by UTF-16 code units, so astral characters sort before
+            // the end of the basic plane
+            (text("\u{ffff}"), text("\u{10000}"), Ordering::Greater),
+        ];
+
+        for (a, b, expected) in cases {
+            assert_eq!(a.compare(&b), expected, "comparing {a:?} with {b:?}");
+            assert_eq!(b.compare(&a), expected.reverse(), "comparing {b:?} with {a:?}");
+        }
+    }
+}
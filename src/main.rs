@@ -1,29 +1,295 @@
-use std::{env::args, fs, process::exit};
+use std::{
+    env::args,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    process::exit,
+    sync::Arc,
+};
 
-use scratch_vm::{ast::project::ScratchProject, interpreter::opcode::Trigger, sb3::Sb3Project};
+use scratch_vm::{
+    ast::project::ScratchProject,
+    blocks::BlockLibrary,
+    interpreter::{
+        Program, Task,
+        clock::VirtualClock,
+        disasm,
+        image::{self, ImageError},
+        input::ScriptedAnswers,
+        observer::ProgramObserver,
+        replay::{self, Recording},
+        rng::Rng,
+        source_map::BlockSource,
+        sprite::{Bubble, BubbleKind},
+        trace::{TextTraceSink, TraceLevel},
+    },
+    sb3::{Sb3Archive, Sb3Project},
+};
+
+/// The file extension of compiled program images.
+const IMAGE_EXTENSION: &str = ".svmi";
+
+enum Command {
+    Run,
+    Disassemble,
+    SaveImage,
+    Replay,
+}
 
 fn main() {
-    let args = args().collect::<Vec<_>>();
-    let Some(sb3_path) = args.get(1) else {
+    let args = args().skip(1).collect::<Vec<_>>();
+    let (flags, paths): (Vec<_>, Vec<_>) = args.iter().partition(|arg| arg.starts_with("--"));
+    let (command, paths) = match paths.split_first() {
+        Some((command, rest)) if *command == "disasm" => (Command::Disassemble, rest),
+        Some((command, rest)) if *command == "image" => (Command::SaveImage, rest),
+        Some((command, rest)) if *command == "replay" => (Command::Replay, rest),
+        _ => (Command::Run, &paths[..]),
+    };
+    let Some(path) = paths.first() else {
         print_usage();
     };
 
-    let sb3_file = fs::read_to_string(sb3_path).unwrap();
+    let mut program = load_program(path, matches!(command, Command::Run));
+
+    match command {
+        Command::Run => {}
+        Command::Disassemble => {
+            if let Err(err) = disasm::disassemble(&program, &mut io::stdout().lock()) {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+            return;
+        }
+        Command::SaveImage => {
+            let Some(image_path) = paths.get(1) else {
+                print_usage();
+            };
+
+            let result = File::create(image_path).and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                image::write_image(&program, &mut writer)?;
+                writer.flush()
+            });
+            if let Err(err) = result {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+            return;
+        }
+        Command::Replay => {
+            let Some(recording_path) = paths.get(1) else {
+                print_usage();
+            };
+
+            let recording: Recording = match fs::read_to_string(recording_path)
+                .map_err(|err| err.to_string())
+                .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()))
+            {
+                Ok(recording) => recording,
+                Err(err) => {
+                    eprintln!("error: failed to read recording: {err}");
+                    exit(1);
+                }
+            };
+
+            add_observers(&mut program);
+            match replay::replay(&mut program, &recording) {
+                Ok(frames) => eprintln!("replay matched the recording for {frames} frames"),
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    exit(1);
+                }
+            }
+            return;
+        }
+    }
+
+    let mut answers = Vec::new();
+    let mut record_path = None;
+    let mut seed = None;
+
+    for flag in flags {
+        match flag.as_str() {
+            "--virtual-clock" => program.set_clock(VirtualClock::new()),
+            "--60fps" => program.set_frame_rate(Program::HIGH_FRAME_RATE),
+            flag if flag.starts_with("--username=") => {
+                program.set_username(&flag["--username=".len()..]);
+            }
+            flag if flag.starts_with("--answer=") => answers.push(&flag["--answer=".len()..]),
+            flag if flag.starts_with("--record=") => record_path = Some(&flag["--record=".len()..]),
+            flag if flag.starts_with("--seed=") => match flag["--seed=".len()..].parse::<u64>() {
+                Ok(value) => seed = Some(value),
+                Err(_) => print_usage(),
+            },
+            flag => {
+                let Some(level) = flag
+                    .strip_prefix("--trace=")
+                    .and_then(TraceLevel::from_name)
+                else {
+                    print_usage();
+                };
 
-    let sb3: Sb3Project = serde_json::from_str(&sb3_file).unwrap();
-    let project = ScratchProject::from(sb3);
-    eprintln!("project: {project:#?}");
-    let mut program = project.compile();
-    eprintln!("program: {program:#?}");
+                program.set_tracer(level, TextTraceSink::new(io::stderr()));
+            }
+        }
+    }
+
+    // Questions are answered in the order they're asked, and with empty text after that
+    program.set_answer_source(ScriptedAnswers::new(answers));
+
+    add_observers(&mut program);
 
-    program.dispatch(Trigger::OnStart);
+    if let Some(seed) = seed {
+        program.seed_rng(seed);
+    }
+
+    // Recordings can only be replayed if time passes the same way, so they always use a
+    // virtual clock
+    if record_path.is_some() {
+        program.set_clock(VirtualClock::new());
+        program.start_recording(seed.unwrap_or_else(|| Rng::from_time().next_u64()));
+    }
+
+    program.click_green_flag();
 
     while program.has_incomplete_tasks() {
         program.run_frame();
     }
+
+    let recording = program.finish_recording();
+    if let (Some(path), Some(recording)) = (record_path, recording) {
+        let result = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, &recording)?;
+            writer.flush()
+        });
+        if let Err(err) = result {
+            eprintln!("error: failed to write recording: {err}");
+            exit(1);
+        }
+    }
+}
+
+fn add_observers(program: &mut Program) {
+    let target_names = program.targets().iter().map(|t| t.name().into()).collect();
+    program.add_observer(BubblePrinter { target_names });
+    program.add_observer(PanicReporter);
+}
+
+/// Loads a program from a compiled image, or compiles it from a project.
+fn load_program(path: &str, verbose: bool) -> Program {
+    if path.ends_with(IMAGE_EXTENSION) {
+        let result = File::open(path)
+            .map_err(ImageError::from)
+            .and_then(|file| image::read_image(&mut BufReader::new(file), BlockLibrary::default()));
+        return match result {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+        };
+    }
+
+    // Bare project.json files are still accepted so that extracted projects keep working.
+    let sb3: Sb3Project = if path.ends_with(".json") {
+        let sb3_file = match fs::read_to_string(path) {
+            Ok(sb3_file) => sb3_file,
+            Err(err) => {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+        };
+        match serde_json::from_str(&sb3_file) {
+            Ok(sb3) => sb3,
+            Err(err) => {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+        }
+    } else {
+        match Sb3Archive::open(path) {
+            Ok(archive) => {
+                eprintln!("loaded {} assets", archive.assets.len());
+                archive.project
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                exit(1);
+            }
+        }
+    };
+
+    let project = match ScratchProject::try_from(sb3) {
+        Ok(project) => project,
+        Err(err) => {
+            eprintln!("error: {err}");
+            exit(1);
+        }
+    };
+
+    if verbose {
+        eprintln!("project: {project:#?}");
+    }
+    let program = project.compile();
+    if verbose {
+        eprintln!("program: {program:#?}");
+    }
+    program
+}
+
+/// Prints what sprites say and think, since there's no stage to show bubbles on.
+#[derive(Debug)]
+struct BubblePrinter {
+    target_names: Vec<Arc<str>>,
+}
+
+impl ProgramObserver for BubblePrinter {
+    fn bubble_changed(&mut self, target_id: usize, bubble: Option<&Bubble>) {
+        let Some(bubble) = bubble else {
+            return;
+        };
+
+        let name = &self.target_names[target_id];
+        match bubble.kind {
+            BubbleKind::Say => println!("{name} says: {}", bubble.text),
+            BubbleKind::Think => println!("{name} thinks: {}", bubble.text),
+        }
+    }
+
+    fn clone_created(&mut self, target_id: usize, parent_id: usize) {
+        let name = self.target_names[parent_id].clone();
+        if target_id < self.target_names.len() {
+            self.target_names[target_id] = name;
+        } else {
+            self.target_names.push(name);
+        }
+    }
+}
+
+/// Notes which block was running when a task panicked, so that the failure can be found
+/// in the project.
+#[derive(Debug)]
+struct PanicReporter;
+
+impl ProgramObserver for PanicReporter {
+    fn task_panicked(&mut self, task: &Task, block: Option<&BlockSource>) {
+        if let Some(block) = block {
+            eprintln!(
+                "note: task {} panicked while running block {:?} ({}) in {}",
+                task.id().get(),
+                block.id,
+                block.opcode,
+                task.script().name(),
+            );
+        }
+    }
 }
 
 fn print_usage() -> ! {
-    eprintln!("\nUsage: scratch-vm <PATH-TO-SB3>");
+    eprintln!("\nUsage: scratch-vm <PATH-TO-SB3-OR-PROJECT-JSON> [--virtual-clock] [--60fps] [--trace=off|broadcasts|opcodes] [--username=NAME] [--answer=TEXT]... [--seed=N] [--record=OUTPUT.json]");
+    eprintln!("       scratch-vm disasm <PATH-TO-SB3-OR-PROJECT-JSON>");
+    eprintln!("       scratch-vm image <PATH-TO-SB3-OR-PROJECT-JSON> <OUTPUT.svmi>");
+    eprintln!("       scratch-vm replay <PATH-TO-SB3-OR-PROJECT-JSON> <RECORDING.json>");
+    eprintln!("\nCompiled images (.svmi) can be used anywhere a project is accepted.");
     exit(1);
 }
@@ -1,13 +1,27 @@
-use std::{collections::HashMap, fmt::Display, mem::take, rc::Rc, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    mem::take,
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+};
 
-use serde::{Deserialize, Serialize, de::value};
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize, de::DeserializeOwned, de::value};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use zip::{ZipArchive, result::ZipError};
 
 use crate::{
     ast::{
-        Block, Event, Field, Input, Script, Target, Variable, VariableRef, project::ScratchProject,
+        Block, Costume, Event, Field, Input, List, ListRef, ProcedureArgument, ProcedurePrototype,
+        RotationStyle, Script, Sound, Sprite, StartCondition, StartConditionError, Target, Variable,
+        VariableRef,
+        project::ScratchProject,
     },
-    interpreter::value::Value,
+    interpreter::value::{Value, format_number},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,19 +29,244 @@ pub struct Sb3Project {
     pub targets: Vec<Sb3Target>,
 }
 
+/// A project loaded from a `.sb3` file, which is a zip archive containing a `project.json`
+/// alongside every costume and sound the project references.
+#[derive(Debug)]
+pub struct Sb3Archive {
+    pub project: Sb3Project,
+    pub assets: AssetStore,
+}
+
+impl Sb3Archive {
+    pub const PROJECT_FILE: &str = "project.json";
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        let file = File::open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    pub fn from_reader(reader: impl Read + Seek) -> Result<Self, ArchiveError> {
+        let mut archive = ZipArchive::new(reader)?;
+
+        let project = {
+            let file = match archive.by_name(Self::PROJECT_FILE) {
+                Ok(file) => file,
+                Err(ZipError::FileNotFound) => return Err(ArchiveError::MissingProject),
+                Err(err) => return Err(err.into()),
+            };
+            serde_json::from_reader(BufReader::new(file))?
+        };
+
+        let mut assets = AssetStore::default();
+
+        for idx in 0..archive.len() {
+            let mut file = archive.by_index(idx)?;
+            if !file.is_file() || file.name() == Self::PROJECT_FILE {
+                continue;
+            }
+
+            let md5ext: Arc<str> = file.name().into();
+            let mut data = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut data)?;
+
+            assets.insert(md5ext, data.into());
+        }
+
+        Ok(Self { project, assets })
+    }
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum ArchiveError {
+    #[display("failed to read project file: {_0}")]
+    Io(io::Error),
+    #[display("project file is not a valid zip archive: {_0}")]
+    Zip(ZipError),
+    #[display("project.json is malformed: {_0}")]
+    Json(serde_json::Error),
+    #[display("project file does not contain a project.json")]
+    #[from(skip)]
+    MissingProject,
+}
+
+/// The asset files bundled in a `.sb3` archive. Assets are stored by their `md5ext` (the
+/// file name in the archive) and can also be looked up by their `assetId`, which is the
+/// same name without the file extension.
+#[derive(Debug, Default, Clone)]
+pub struct AssetStore {
+    files: HashMap<Arc<str>, Arc<[u8]>>,
+    ids: HashMap<Arc<str>, Arc<str>>,
+}
+
+impl AssetStore {
+    pub fn insert(&mut self, md5ext: Arc<str>, data: Arc<[u8]>) {
+        let asset_id = md5ext
+            .rsplit_once('.')
+            .map_or(md5ext.clone(), |(id, _ext)| id.into());
+
+        self.ids.insert(asset_id, md5ext.clone());
+        self.files.insert(md5ext, data);
+    }
+
+    pub fn get(&self, md5ext: &str) -> Option<Arc<[u8]>> {
+        self.files.get(md5ext).cloned()
+    }
+
+    pub fn get_by_id(&self, asset_id: &str) -> Option<Arc<[u8]>> {
+        self.ids.get(asset_id).and_then(|md5ext| self.get(md5ext))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Sb3Target {
     is_stage: bool,
     name: Arc<str>,
     variables: HashMap<Arc<str>, Sb3Variable>,
+    #[serde(default)]
+    lists: HashMap<Arc<str>, Sb3List>,
     broadcasts: HashMap<Arc<str>, Arc<str>>,
     blocks: HashMap<Arc<str>, Sb3Block>,
+    #[serde(default)]
+    costumes: Vec<Sb3Costume>,
+    #[serde(default)]
+    sounds: Vec<Sb3Sound>,
+    #[serde(default)]
+    current_costume: usize,
+    #[serde(default)]
+    layer_order: usize,
+    // The rest of these properties are only present on sprites.
+    x: Option<f64>,
+    y: Option<f64>,
+    direction: Option<f64>,
+    size: Option<f64>,
+    visible: Option<bool>,
+    draggable: Option<bool>,
+    rotation_style: Option<Arc<str>>,
+}
+
+impl Sb3Target {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn costumes(&self) -> &[Sb3Costume] {
+        &self.costumes
+    }
+
+    pub fn sounds(&self) -> &[Sb3Sound] {
+        &self.sounds
+    }
+
+    fn sprite(&self) -> Option<Sprite> {
+        if self.is_stage {
+            return None;
+        }
+
+        let default = Sprite::default();
+        Some(Sprite {
+            x: self.x.unwrap_or(default.x),
+            y: self.y.unwrap_or(default.y),
+            direction: self.direction.unwrap_or(default.direction),
+            size: self.size.unwrap_or(default.size),
+            visible: self.visible.unwrap_or(default.visible),
+            draggable: self.draggable.unwrap_or(default.draggable),
+            rotation_style: self
+                .rotation_style
+                .as_deref()
+                .and_then(RotationStyle::from_name)
+                .unwrap_or(default.rotation_style),
+            layer_order: self.layer_order,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Costume {
+    #[serde(flatten)]
+    pub asset: Sb3Asset,
+    pub bitmap_resolution: Option<f64>,
+    #[serde(default)]
+    pub rotation_center_x: f64,
+    #[serde(default)]
+    pub rotation_center_y: f64,
+}
+
+impl From<&Sb3Costume> for Costume {
+    fn from(value: &Sb3Costume) -> Self {
+        Self {
+            name: value.asset.name.clone(),
+            asset_id: value.asset.asset_id.clone(),
+            md5ext: value.asset.md5ext(),
+            bitmap_resolution: value.bitmap_resolution.unwrap_or(1.0),
+            rotation_center: (value.rotation_center_x, value.rotation_center_y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Sound {
+    #[serde(flatten)]
+    pub asset: Sb3Asset,
+    pub rate: Option<u32>,
+    pub sample_count: Option<u32>,
+}
+
+impl From<&Sb3Sound> for Sound {
+    fn from(value: &Sb3Sound) -> Self {
+        Self {
+            name: value.asset.name.clone(),
+            asset_id: value.asset.asset_id.clone(),
+            md5ext: value.asset.md5ext(),
+            rate: value.rate.unwrap_or_default(),
+            sample_count: value.sample_count.unwrap_or_default(),
+        }
+    }
+}
+
+/// The metadata shared by costumes and sounds which is used to locate their data in an
+/// [`AssetStore`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Asset {
+    pub asset_id: Arc<str>,
+    pub name: Arc<str>,
+    pub md5ext: Option<Arc<str>>,
+    pub data_format: Arc<str>,
+}
+
+impl Sb3Asset {
+    /// The name of this asset's file in the archive. Older projects may omit `md5ext`,
+    /// in which case it's derived from the asset id and format.
+    pub fn md5ext(&self) -> Arc<str> {
+        self.md5ext
+            .clone()
+            .unwrap_or_else(|| format!("{}.{}", self.asset_id, self.data_format).into())
+    }
+
+    pub fn load(&self, assets: &AssetStore) -> Option<Arc<[u8]>> {
+        assets
+            .get(&self.md5ext())
+            .or_else(|| assets.get_by_id(&self.asset_id))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Sb3Variable(Arc<str>, Sb3Value);
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sb3List(Arc<str>, Vec<Sb3Value>);
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Sb3Value {
@@ -48,7 +287,7 @@ impl Display for Sb3Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
             Self::String(str) => str.to_string(),
-            Self::Number(num) => num.to_string(),
+            Self::Number(num) => format_number(*num),
         };
         write!(f, "{str}")
     }
@@ -69,16 +308,68 @@ pub struct Sb3Block {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Sb3Mutation {
     #[serde(rename = "proccode")]
-    proc_code: Arc<str>,
-    #[serde(flatten)]
-    prototype: Option<Sb3MutationPrototype>,
+    proc_code: Option<Arc<str>>,
+    // The argument lists are JSON arrays encoded as strings.
+    #[serde(rename = "argumentids")]
+    argument_ids: Option<Arc<str>>,
+    #[serde(rename = "argumentnames")]
+    argument_names: Option<Arc<str>>,
+    #[serde(rename = "argumentdefaults")]
+    argument_defaults: Option<Arc<str>>,
+    warp: Option<Sb3Bool>,
 }
 
+impl Sb3Mutation {
+    fn to_prototype(&self, proc_code: Arc<str>) -> Result<ProcedurePrototype, serde_json::Error> {
+        fn decode<T: DeserializeOwned>(array: &Option<Arc<str>>) -> serde_json::Result<Vec<T>> {
+            array.as_deref().map_or(Ok(vec![]), serde_json::from_str)
+        }
+
+        let ids: Vec<Arc<str>> = decode(&self.argument_ids)?;
+        let names: Vec<Arc<str>> = decode(&self.argument_names)?;
+        // Defaults are usually strings, but boolean arguments may use `false`.
+        let defaults: Vec<serde_json::Value> = decode(&self.argument_defaults)?;
+
+        if ids.len() != names.len() {
+            return Err(serde::de::Error::custom(format!(
+                "prototype has {} argument ids but {} argument names",
+                ids.len(),
+                names.len()
+            )));
+        }
+
+        let mut prototype = ProcedurePrototype::new(proc_code);
+        prototype.warp = self.warp.as_ref().is_some_and(Sb3Bool::get);
+
+        for (idx, (id, name)) in ids.into_iter().zip(names).enumerate() {
+            let mut arg = ProcedureArgument::new(id, name);
+            match defaults.get(idx) {
+                Some(serde_json::Value::String(default)) => arg = arg.with_default(&**default),
+                Some(default) => arg = arg.with_default(default.to_string()),
+                None => {}
+            }
+            prototype = prototype.with_arg(arg);
+        }
+
+        Ok(prototype)
+    }
+}
+
+/// A boolean which may be encoded as a string, as is the case for mutations.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct Sb3MutationPrototype {
-    #[serde(rename = "argumentdefaults")]
-    argument_defaults: Arc<str>,
-    warp: Arc<str>,
+#[serde(untagged)]
+pub enum Sb3Bool {
+    Bool(bool),
+    String(Arc<str>),
+}
+
+impl Sb3Bool {
+    pub fn get(&self) -> bool {
+        match self {
+            Self::Bool(bool) => *bool,
+            Self::String(string) => &**string == "true",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -86,13 +377,20 @@ pub struct Sb3MutationPrototype {
 pub enum Sb3BlockRef {
     Ref(Arc<str>),
     InlinePrimitive(Sb3InlineBlock),
+    /// An input slot that doesn't contain a block, like an empty `if` condition.
+    Empty,
 }
 
 impl Sb3BlockRef {
-    fn take_inner(self, blocks: &mut HashMap<Arc<str>, Sb3Block>) -> Vec<Block> {
+    fn take_inner(
+        self,
+        blocks: &mut HashMap<Arc<str>, Sb3Block>,
+        referrer: &BlockLocation,
+    ) -> Result<Vec<Block>, LoadError> {
         match self {
-            Self::Ref(block_id) => deserialize_substack(block_id, blocks),
-            Self::InlinePrimitive(block) => vec![block.into()],
+            Self::Ref(block_id) => deserialize_substack(block_id, blocks, referrer),
+            Self::InlinePrimitive(block) => Ok(vec![block.into_block(referrer)?]),
+            Self::Empty => Ok(vec![]),
         }
     }
 }
@@ -105,7 +403,7 @@ pub struct Sb3InlineBlock(
     #[serde(default)] Option<Arc<str>>,
 );
 
-#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[derive(Debug, Clone, Copy, Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 pub enum Sb3InlineBlockType {
     Number = 4,
@@ -156,13 +454,69 @@ impl From<Sb3Field> for Field {
     }
 }
 
-impl From<Sb3Project> for ScratchProject {
-    fn from(mut project: Sb3Project) -> Self {
-        let mut stage = project
+/// Identifies a block in a project so that load errors can point at the offending block.
+#[derive(Debug, Clone, Display)]
+#[display("block {block_id:?} ({opcode}) in target {target:?}")]
+pub struct BlockLocation {
+    pub target: Arc<str>,
+    pub block_id: Arc<str>,
+    pub opcode: Arc<str>,
+}
+
+impl BlockLocation {
+    pub fn new(target: Arc<str>, block_id: Arc<str>, opcode: Arc<str>) -> Self {
+        Self {
+            target,
+            block_id,
+            opcode,
+        }
+    }
+}
+
+/// An error encountered while converting a deserialized sb3 project into an AST.
+#[derive(Debug, Display, Error)]
+pub enum LoadError {
+    #[display("project does not have a stage")]
+    MissingStage,
+    #[display("{referrer} references missing block {missing_id:?}")]
+    MissingBlock {
+        referrer: BlockLocation,
+        #[error(not(source))]
+        missing_id: Arc<str>,
+    },
+    #[display("{location} has a substack as the shadow of input {input:?}")]
+    ShadowSubstack {
+        location: BlockLocation,
+        #[error(not(source))]
+        input: Arc<str>,
+    },
+    #[display("{location} contains an inline {kind:?} without an id")]
+    MissingPrimitiveId {
+        location: BlockLocation,
+        #[error(not(source))]
+        kind: Sb3InlineBlockType,
+    },
+    #[display("{location} has a malformed mutation: {source}")]
+    MalformedMutation {
+        location: BlockLocation,
+        source: serde_json::Error,
+    },
+    #[display("{location} is not a valid script start: {source}")]
+    InvalidStartCondition {
+        location: BlockLocation,
+        source: StartConditionError,
+    },
+}
+
+impl TryFrom<Sb3Project> for ScratchProject {
+    type Error = LoadError;
+
+    fn try_from(mut project: Sb3Project) -> Result<Self, Self::Error> {
+        let stage = project
             .targets
             .iter_mut()
             .find(|t| t.is_stage)
-            .expect("project must have stage");
+            .ok_or(LoadError::MissingStage)?;
 
         let events = stage
             .broadcasts
@@ -170,25 +524,36 @@ impl From<Sb3Project> for ScratchProject {
             .map(|(id, name)| (id.clone(), Event::new(id.clone(), name.clone())))
             .collect();
 
-        let global_vars = deserialize_variables(&mut stage);
+        let global_vars = deserialize_variables(stage);
+        let global_lists = deserialize_lists(stage);
 
         let targets = project
             .targets
             .into_iter()
             .map(|mut t| {
-                let scripts = build_scripts(&mut t);
+                let scripts = build_scripts(&mut t)?;
                 let variables = deserialize_variables(&mut t);
+                let lists = deserialize_lists(&mut t);
 
-                Target {
+                Ok(Target {
+                    sprite: t.sprite(),
+                    costumes: t.costumes.iter().map(Costume::from).collect(),
+                    sounds: t.sounds.iter().map(Sound::from).collect(),
+                    current_costume: t.current_costume,
                     name: t.name,
                     variables,
-                    sprite: None,
+                    lists,
                     scripts,
-                }
+                })
             })
-            .collect();
+            .collect::<Result<_, LoadError>>()?;
 
-        Self { events, targets, global_vars }
+        Ok(Self {
+            events,
+            targets,
+            global_vars,
+            global_lists,
+        })
     }
 }
 
@@ -203,12 +568,30 @@ fn deserialize_variables(target: &mut Sb3Target) -> HashMap<Arc<str>, Variable>
         .collect()
 }
 
-impl From<Sb3InlineBlock> for Block {
-    fn from(value: Sb3InlineBlock) -> Self {
-        let id = value.2;
-        let inner = value.1;
+fn deserialize_lists(target: &mut Sb3Target) -> HashMap<Arc<str>, List> {
+    target
+        .lists
+        .drain()
+        .map(|(id, list)| {
+            let items = list.1.into_iter().map(Value::from).collect();
+            let list = List::new(ListRef::new(id.clone(), list.0), items);
+            (id, list)
+        })
+        .collect()
+}
 
-        match value.0 {
+impl Sb3InlineBlock {
+    fn into_block(self, location: &BlockLocation) -> Result<Block, LoadError> {
+        let kind = self.0;
+        let inner = self.1;
+        let id = || {
+            self.2.clone().ok_or_else(|| LoadError::MissingPrimitiveId {
+                location: location.clone(),
+                kind,
+            })
+        };
+
+        Ok(match kind {
             Sb3InlineBlockType::Number => Block::number(inner),
             Sb3InlineBlockType::PositiveNumber => Block::pos_number(inner),
             Sb3InlineBlockType::WholeNumber => Block::whole_number(inner),
@@ -216,36 +599,45 @@ impl From<Sb3InlineBlock> for Block {
             Sb3InlineBlockType::Angle => Block::angle(inner),
             Sb3InlineBlockType::Color => Block::color(inner),
             Sb3InlineBlockType::Text => Block::text(inner),
-            Sb3InlineBlockType::Broadcast => Block::event(id.unwrap(), inner),
-            Sb3InlineBlockType::Variable => Block::var(id.unwrap(), inner),
-            Sb3InlineBlockType::List => unimplemented!(),
-        }
+            Sb3InlineBlockType::Broadcast => Block::event(id()?, inner),
+            Sb3InlineBlockType::Variable => Block::var(id()?, inner),
+            Sb3InlineBlockType::List => Block::list(id()?, inner),
+        })
     }
 }
 
-fn build_scripts(target: &mut Sb3Target) -> Vec<Script> {
+fn build_scripts(target: &mut Sb3Target) -> Result<Vec<Script>, LoadError> {
     let mut scripts = vec![];
 
-    let top_level_block_ids = target
+    let top_level_blocks = target
         .blocks
         .iter()
         .filter(|(_id, block)| block.top_level)
-        .map(|(id, _block)| id.clone())
+        .map(|(id, block)| BlockLocation::new(target.name.clone(), id.clone(), block.opcode.clone()))
         .collect::<Vec<_>>();
 
-    for block_id in top_level_block_ids {
-        let mut substack = deserialize_substack(block_id, &mut target.blocks);
+    for location in top_level_blocks {
+        let mut substack =
+            deserialize_substack(location.block_id.clone(), &mut target.blocks, &location)?;
+
+        let start_condition = substack[0].try_as_start_condition().map_err(|source| {
+            LoadError::InvalidStartCondition {
+                location: location.clone(),
+                source,
+            }
+        })?;
 
-        let Some(start_condition) = substack[0].try_as_start_condition() else {
+        let Some(start_condition) = start_condition else {
             eprintln!("WARN: Script missing start condition");
-            eprintln!(
-                "    > Triggered by top-level block {:?}",
-                substack[0].opcode
-            );
+            eprintln!("    > Triggered by top-level {location}");
             continue;
         };
 
-        substack.remove(0);
+        // Edge-activated hats are compiled like other blocks so that they can check their
+        // condition whenever the script starts
+        if !matches!(start_condition, StartCondition::EdgeActivated) {
+            substack.remove(0);
+        }
 
         scripts.push(Script {
             start_condition,
@@ -253,23 +645,41 @@ fn build_scripts(target: &mut Sb3Target) -> Vec<Script> {
         });
     }
 
-    scripts
+    Ok(scripts)
 }
 
-fn deserialize_substack(start_id: Arc<str>, blocks: &mut HashMap<Arc<str>, Sb3Block>) -> Vec<Block> {
+fn deserialize_substack(
+    start_id: Arc<str>,
+    blocks: &mut HashMap<Arc<str>, Sb3Block>,
+    referrer: &BlockLocation,
+) -> Result<Vec<Block>, LoadError> {
     let mut substack = vec![];
     let mut next_id = Some(start_id);
+    let mut referrer = referrer.clone();
 
     while let Some(id) = next_id {
-        let mut block = blocks.remove(&id).expect("missing block");
-        substack.push(deserialize_block(&mut block, blocks));
+        let Some(mut block) = blocks.remove(&id) else {
+            return Err(LoadError::MissingBlock {
+                referrer,
+                missing_id: id,
+            });
+        };
+
+        let location = BlockLocation::new(referrer.target.clone(), id, block.opcode.clone());
+        substack.push(deserialize_block(&mut block, &location, blocks)?);
+
         next_id = block.next;
+        referrer = location;
     }
 
-    substack
+    Ok(substack)
 }
 
-fn deserialize_block(block: &mut Sb3Block, other_blocks: &mut HashMap<Arc<str>, Sb3Block>) -> Block {
+fn deserialize_block(
+    block: &mut Sb3Block,
+    location: &BlockLocation,
+    other_blocks: &mut HashMap<Arc<str>, Sb3Block>,
+) -> Result<Block, LoadError> {
     let fields = take(&mut block.fields)
         .into_iter()
         .map(|(name, f)| (name, f.into()))
@@ -278,26 +688,69 @@ fn deserialize_block(block: &mut Sb3Block, other_blocks: &mut HashMap<Arc<str>,
     let inputs = take(&mut block.inputs)
         .into_iter()
         .map(|(name, input)| {
-            let blocks = input.1.take_inner(other_blocks);
-
-            let shadow = input.2.map(|shadow| {
-                let mut substack = shadow.take_inner(other_blocks);
-                assert!(substack.len() == 1, "shadows cannot be substacks");
-
-                substack.remove(0)
-            });
-
-            (name, Input { blocks, shadow })
+            let blocks = input.1.take_inner(other_blocks, location)?;
+
+            let shadow = input
+                .2
+                .map(|shadow| {
+                    let mut substack = shadow.take_inner(other_blocks, location)?;
+                    if substack.len() != 1 {
+                        return Err(LoadError::ShadowSubstack {
+                            location: location.clone(),
+                            input: name.clone(),
+                        });
+                    }
+
+                    Ok(substack.remove(0))
+                })
+                .transpose()?;
+
+            Ok((name, Input { blocks, shadow }))
         })
-        .collect();
+        .collect::<Result<_, LoadError>>()?;
+
+    let proc_code = block
+        .mutation
+        .as_ref()
+        .and_then(|mutation| mutation.proc_code.clone());
+
+    let prototype = match (&block.mutation, &proc_code) {
+        (Some(mutation), Some(proc_code)) if &*block.opcode == Block::PROCEDURE_PROTOTYPE => {
+            let prototype = mutation.to_prototype(proc_code.clone()).map_err(|source| {
+                LoadError::MalformedMutation {
+                    location: location.clone(),
+                    source,
+                }
+            })?;
+            Some(prototype)
+        }
+        _ => None,
+    };
 
-    Block {
+    Ok(Block {
+        id: Some(location.block_id.clone()),
         opcode: take(&mut block.opcode),
-        proc_code: block
-            .mutation
-            .as_ref()
-            .map(|mutation| mutation.proc_code.clone()),
+        proc_code,
+        prototype,
         fields,
         inputs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sb3Mutation;
+
+    #[test]
+    fn prototype_argument_defaults() {
+        let mutation: Sb3Mutation = serde_json::from_str(
+            r#"{"proccode": "f %s %b %s", "argumentids": "[\"a\", \"b\", \"c\"]",
+                "argumentnames": "[\"x\", \"y\", \"z\"]", "argumentdefaults": "[\"1\", false]"}"#,
+        )
+        .unwrap();
+        let prototype = mutation.to_prototype("f %s %b %s".into()).unwrap();
+
+        let defaults: Vec<&str> = prototype.arguments.iter().map(|a| &*a.default).collect();
+        assert_eq!(defaults, ["1", "false", ""]);
     }
 }